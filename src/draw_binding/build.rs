@@ -2,7 +2,25 @@ extern crate capnpc;
 
 #[cfg(feature="build-schema")]
 fn main() {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::Command;
+
     ::capnpc::compile("draw_binding", &["draw_binding.capnp"]).unwrap();
+
+    // the CodeGeneratorRequest is embedded in the hairballs the column
+    // is written to so that it can be read without this crate
+    let request = Command::new("capnp")
+        .arg("compile")
+        .arg("-o-")
+        .arg("draw_binding.capnp")
+        .output()
+        .unwrap();
+    assert!(request.status.success(), "capnp compile failed");
+    let out = std::env::var("OUT_DIR").unwrap();
+    File::create(Path::new(&out).join("draw_binding.schema")).unwrap()
+        .write_all(&request.stdout[..]).unwrap();
 }
 
 #[cfg(not(feature="build-schema"))]
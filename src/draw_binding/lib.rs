@@ -1,4 +1,5 @@
 extern crate capnp;
+#[macro_use]
 extern crate hairball;
 
 #[cfg(feature="build-schema")]
@@ -9,84 +10,85 @@ pub mod draw_binding_capnp {
 #[cfg(not(feature="build-schema"))]
 pub mod draw_binding_capnp;
 
-const COLUMN_NAME: &'static str = "draw_binding";
+/// The name the column is stored under in a hairball
+pub const COLUMN_NAME: &'static str = "draw_binding";
 
-#[derive(Copy, Debug, Clone)]
-pub struct DrawBinding<E> {
-    pub geometry: E,
-    pub material: E
+/// The schema of the column as a serialized CodeGeneratorRequest, this is
+/// only available if the crate was built with `build-schema`
+#[cfg(feature="build-schema")]
+pub fn schema() -> Option<&'static [u8]> {
+    Some(include_bytes!(concat!(env!("OUT_DIR"), "/draw_binding.schema")))
 }
 
-/// Write out a column into a hairball
-pub fn write(hb: &mut hairball::Builder, i: &[(u32, DrawBinding<u32>)]) {
-    let column: draw_binding_capnp::column::Builder = hb.column(COLUMN_NAME)
-        .and_then(|c| c.get_as())
-        .unwrap();
-
-    let mut rows = column.init_bindings(i.len() as u32);
-    for (i, &(name, geo)) in i.iter().enumerate() {
-        let mut row = rows.borrow().get(i as u32);
-        row.set_id(name);
-        row.set_geometry(geo.geometry);
-        row.set_material(geo.material);
-    }
+#[cfg(not(feature="build-schema"))]
+pub fn schema() -> Option<&'static [u8]> {
+    None
 }
 
-impl<'a, E> Iterator for Reader<'a, E> {
-    type Item = (&'a E, DrawBinding<&'a E>);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        for i in &mut self.index {
-            let row = self.column.get(i);
-
-            let id = match self.reader.entity(row.get_id() as usize) {
-                Some(id) => id,
-                None => continue
-            };
-
-            let geometry = match self.reader.entity(row.get_geometry() as usize) {
-                Some(geometry) => geometry,
-                None => continue
-            };
-
-            let material = match self.reader.entity(row.get_material() as usize) {
-                Some(material) => material,
-                None => continue
-            };
-
-            return Some((
-                id,
-                DrawBinding{
-                    geometry: geometry,
-                    material: material
-                }
-            ));
+entity_column! {
+    draw_binding_capnp::column.get_bindings/init_bindings[draw_binding],
+    #[derive(Copy, Debug, Clone)]
+    pub struct DrawBinding {
+        entities {
+            geometry: get_geometry / set_geometry,
+            material: get_material / set_material
         }
-        None
+        values {}
     }
 }
 
-/// Used to read geometry colimn from the hairball
-pub struct Reader<'a, E:'a> {
-    reader: &'a hairball::ReaderMapping<'a, E>,
-    column: capnp::struct_list::Reader<'a, draw_binding_capnp::draw_binding::Owned>,
-    index: std::ops::Range<u32>
-}
-
-/// Create a column reader for iff the hairball has a valid geometry
-/// column set
-pub fn read<'a, E>(read: &'a hairball::ReaderMapping<'a, E>) -> Option<Reader<'a, E>>
+/// Read the column from every layer of an overlay, the rows of an entity
+/// in a later layer replace it's rows in earlier layers
+pub fn read_overlay<'a, E>(overlay: &'a hairball::OverlayMapping<'a, E>)
+    -> Option<Box<Iterator<Item=(&'a E, DrawBinding<&'a E>)> + 'a>>
     where E: 'a
 {
-    read.column(COLUMN_NAME)
-        .and_then(|c| c.get_as().ok() )
-        .and_then(|c: draw_binding_capnp::column::Reader<'a>| c.get_bindings().ok( ))
-        .map(|c| {
-            let len = c.len();
-            Reader{
-                reader: read,
-                column: c,
-                index: (0..len)
-            }
+    overlay.column(COLUMN_NAME, |layer| {
+        read(layer).map(|rows| {
+            Box::new(rows.map(|(&id, b)| {
+                (id, DrawBinding {
+                    geometry: *b.geometry,
+                    material: *b.material
+                })
+            })) as Box<Iterator<Item=(usize, DrawBinding<usize>)> + 'a>
         })
+    }).map(|rows| {
+        Box::new(rows.filter_map(move |(id, b)| {
+            match (overlay.entity(b.geometry), overlay.entity(b.material)) {
+                (Some(geometry), Some(material)) => Some((id, DrawBinding {
+                    geometry: geometry,
+                    material: material
+                })),
+                _ => None
+            }
+        })) as Box<Iterator<Item=(&'a E, DrawBinding<&'a E>)> + 'a>
+    })
+}
+
+/// Read the draw binding column of `reader`, the geometry and material
+/// of each binding are resolved through the library so they may be in
+/// another hairball. Rows that could not be resolved are skipped.
+pub fn read_linked<'a>(library: &'a hairball::Library, reader: &'a hairball::Reader)
+    -> Option<Box<Iterator<Item=(u32, DrawBinding<hairball::Link<'a>>)> + 'a>>
+{
+    let chunks: Vec<_> = reader.chunks(COLUMN_NAME).into_iter()
+        .filter_map(|c| c.data.get_as().ok())
+        .filter_map(|c: draw_binding_capnp::column::Reader<'a>| c.get_bindings().ok())
+        .collect();
+    if chunks.len() == 0 {
+        return None;
+    }
+
+    let rows = chunks.into_iter().flat_map(|rows| (0..rows.len()).map(move |i| rows.get(i)));
+    Some(Box::new(rows.filter_map(move |row| {
+        let geometry = library.resolve(reader, row.get_geometry());
+        let material = library.resolve(reader, row.get_material());
+        match (geometry, material) {
+            (Some(geometry), Some(material)) => Some((row.get_id(), DrawBinding {
+                geometry: geometry,
+                material: material
+            })),
+            _ => None
+        }
+    })) as Box<Iterator<Item=(u32, DrawBinding<hairball::Link<'a>>)> + 'a>)
 }
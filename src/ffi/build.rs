@@ -0,0 +1,19 @@
+extern crate cheddar;
+
+#[cfg(feature="build-header")]
+fn main() {
+    // the handle is opaque and the material values are plain integers,
+    // neither can be described by cheddar so they are added by hand
+    cheddar::Cheddar::new().expect("could not read manifest")
+        .insert_code("#include <stddef.h>\n\n")
+        .insert_code("typedef struct Hairball Hairball;\n\n")
+        .insert_code("#define HAIRBALL_AMBIENT 0\n")
+        .insert_code("#define HAIRBALL_DIFFUSE 1\n")
+        .insert_code("#define HAIRBALL_SPECULAR 2\n\n")
+        .insert_code("#define HAIRBALL_COLOR 0\n")
+        .insert_code("#define HAIRBALL_TEXTURE 1\n\n")
+        .run_build("include/hairball.h");
+}
+
+#[cfg(not(feature="build-header"))]
+fn main() {}
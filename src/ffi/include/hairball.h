@@ -0,0 +1,149 @@
+#ifndef cheddar_generated_hairball_h
+#define cheddar_generated_hairball_h
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#include <stdint.h>
+#include <stdbool.h>
+
+
+
+#include <stddef.h>
+
+typedef struct Hairball Hairball;
+
+#define HAIRBALL_AMBIENT 0
+#define HAIRBALL_DIFFUSE 1
+#define HAIRBALL_SPECULAR 2
+
+#define HAIRBALL_COLOR 0
+#define HAIRBALL_TEXTURE 1
+
+/// A local or external entity
+typedef struct HairballEntity {
+	/// The name of a local entity or the path of an external entity,
+	/// null if the entity has none
+	char const* name;
+	size_t name_len;
+	bool has_parent;
+	uint32_t parent;
+	/// true if the entity is in another hairball, `file` is it's uuid
+	bool external;
+	uint8_t file[16];
+	bool has_guid;
+	uint8_t guid[16];
+} HairballEntity;
+
+/// A vertex buffer of a mesh, the vertices are interlaced
+typedef struct HairballBuffer {
+	uint8_t const* data;
+	size_t data_len;
+	/// The size of a vertex in bytes
+	uint32_t stride;
+	uint32_t attributes;
+} HairballBuffer;
+
+/// An attribute of a vertex buffer
+typedef struct HairballAttribute {
+	char const* name;
+	size_t name_len;
+	/// The name of the type, such as `f32` or `normalizedU8`
+	char const* element_type;
+	size_t element_type_len;
+	uint8_t element_count;
+	/// The offset of the attribute from the start of a vertex in bytes
+	uint32_t offset;
+} HairballAttribute;
+
+typedef struct HairballGeometry {
+	uint32_t mesh;
+	uint32_t start;
+	uint32_t length;
+} HairballGeometry;
+
+typedef struct HairballDrawBinding {
+	uint32_t geometry;
+	uint32_t material;
+} HairballDrawBinding;
+
+/// A component of a material, `component` is one of `HAIRBALL_AMBIENT`,
+/// `HAIRBALL_DIFFUSE` or `HAIRBALL_SPECULAR`. `kind` is `HAIRBALL_COLOR`
+/// or `HAIRBALL_TEXTURE`, only the matching value is set.
+typedef struct HairballMaterial {
+	uint32_t component;
+	uint32_t kind;
+	float color[4];
+	uint32_t texture;
+} HairballMaterial;
+
+/// Open the hairball at `path`, returns null if it could not be read
+Hairball* hairball_open(char const* path);
+
+/// Close a hairball that was opened with `hairball_open`
+void hairball_close(Hairball* hb);
+
+/// Copy the 16 bytes of the hairball's uuid into `uuid`
+bool hairball_uuid(Hairball const* hb, uint8_t* uuid);
+
+uint64_t hairball_revision(Hairball const* hb);
+
+/// The number of entities, they are numbered from 0
+uint32_t hairball_entities_len(Hairball const* hb);
+
+/// Read an entity, returns false if it does not exist
+bool hairball_entity(Hairball const* hb, uint32_t entity, HairballEntity* out);
+
+/// Write the path of an entity into `buf` like `snprintf`, the path is
+/// truncated and nul terminated if `buf` is too small. Returns the length
+/// of the whole path, 0 if the entity does not have a path.
+size_t hairball_entity_path(Hairball const* hb, uint32_t entity, char* buf, size_t len);
+
+/// Find an entity by it's path
+bool hairball_lookup(Hairball const* hb, char const* path, uint32_t* entity);
+
+/// The number of columns in the hairball, the tombstones of a column are
+/// not counted
+uint32_t hairball_columns_len(Hairball const* hb);
+
+/// Get the name of a column, columns are numbered from 0
+bool hairball_column_name(Hairball const* hb, uint32_t column, char const** name, size_t* len);
+
+/// Check if the hairball has a column with the name
+bool hairball_has_column(Hairball const* hb, char const* name);
+
+/// The number of vertex buffers in the mesh of an entity, 0 if the
+/// entity does not have a mesh
+uint32_t hairball_mesh_buffers(Hairball const* hb, uint32_t entity);
+
+/// Read a vertex buffer of the mesh of an entity
+bool hairball_mesh_buffer(Hairball const* hb, uint32_t entity, uint32_t buffer, HairballBuffer* out);
+
+/// Read an attribute of a vertex buffer of the mesh of an entity
+bool hairball_mesh_attribute(Hairball const* hb, uint32_t entity, uint32_t buffer, uint32_t attribute, HairballAttribute* out);
+
+/// Copy the index list of an entity into `out`, at most `len` indices are
+/// copied. Returns the length of the whole list, 0 if the entity does
+/// not have one.
+size_t hairball_mesh_index(Hairball const* hb, uint32_t entity, uint32_t* out, size_t len);
+
+/// Read the geometry of an entity
+bool hairball_geometry(Hairball const* hb, uint32_t entity, HairballGeometry* out);
+
+/// Read the draw binding of an entity
+bool hairball_draw_binding(Hairball const* hb, uint32_t entity, HairballDrawBinding* out);
+
+/// Copy the material components of an entity into `out`, at most `len`
+/// are copied. Returns the number of components the entity has.
+size_t hairball_materials(Hairball const* hb, uint32_t entity, HairballMaterial* out, size_t len);
+
+
+
+#ifdef __cplusplus
+}
+#endif
+
+
+#endif
@@ -0,0 +1,454 @@
+//! A C interface for reading hairballs, see `include/hairball.h`.
+//!
+//! A hairball is opened with `hairball_open` and must be released with
+//! `hairball_close`. Every pointer returned by these functions points
+//! into the hairball and is valid until it is closed. Strings are not
+//! nul terminated, they are returned as a pointer and a length.
+//!
+//! The known columns are read by entity. A column that is sorted by
+//! entity is binary searched, an unsorted one is scanned, so callers that
+//! read every row should cache the results. A panic inside of a function
+//! is caught and reported the same way as a missing value.
+
+extern crate libc;
+extern crate hairball;
+extern crate hairball_mesh;
+extern crate hairball_mesh_index;
+extern crate hairball_material;
+extern crate hairball_geometry;
+extern crate hairball_draw_binding;
+
+use std::ffi::CStr;
+use std::ptr;
+use libc::{c_char, size_t};
+
+use hairball::{Link, Reader};
+use hairball_material::{Component, Value};
+
+/// An open hairball, this is opaque to C
+pub struct Hairball {
+    reader: Reader
+}
+
+/// A local or external entity
+#[repr(C)]
+pub struct HairballEntity {
+    /// The name of a local entity or the path of an external entity,
+    /// null if the entity has none
+    pub name: *const c_char,
+    pub name_len: size_t,
+    pub has_parent: bool,
+    pub parent: u32,
+    /// true if the entity is in another hairball, `file` is it's uuid
+    pub external: bool,
+    pub file: [u8; 16],
+    pub has_guid: bool,
+    pub guid: [u8; 16]
+}
+
+/// A vertex buffer of a mesh, the vertices are interlaced
+#[repr(C)]
+pub struct HairballBuffer {
+    pub data: *const u8,
+    pub data_len: size_t,
+    /// The size of a vertex in bytes
+    pub stride: u32,
+    pub attributes: u32
+}
+
+/// An attribute of a vertex buffer
+#[repr(C)]
+pub struct HairballAttribute {
+    pub name: *const c_char,
+    pub name_len: size_t,
+    /// The name of the type, such as `f32` or `normalizedU8`
+    pub element_type: *const c_char,
+    pub element_type_len: size_t,
+    pub element_count: u8,
+    /// The offset of the attribute from the start of a vertex in bytes
+    pub offset: u32
+}
+
+#[repr(C)]
+pub struct HairballGeometry {
+    pub mesh: u32,
+    pub start: u32,
+    pub length: u32
+}
+
+#[repr(C)]
+pub struct HairballDrawBinding {
+    pub geometry: u32,
+    pub material: u32
+}
+
+/// A component of a material, `component` is one of `HAIRBALL_AMBIENT`,
+/// `HAIRBALL_DIFFUSE` or `HAIRBALL_SPECULAR`. `kind` is `HAIRBALL_COLOR`
+/// or `HAIRBALL_TEXTURE`, only the matching value is set.
+#[repr(C)]
+pub struct HairballMaterial {
+    pub component: u32,
+    pub kind: u32,
+    pub color: [f32; 4],
+    pub texture: u32
+}
+
+/// Borrow the hairball behind a handle, None if it is null
+unsafe fn get<'a>(hb: *const Hairball) -> Option<&'a Reader> {
+    if hb.is_null() {
+        None
+    } else {
+        Some(&(*hb).reader)
+    }
+}
+
+/// Borrow a nul terminated string, None if it is null or not utf-8
+unsafe fn string<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        std::str::from_utf8(CStr::from_ptr(s).to_bytes()).ok()
+    }
+}
+
+fn copy_uuid(dst: &mut [u8; 16], src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src.iter()) {
+        *d = *s;
+    }
+}
+
+/// Run `f`, a panic must not unwind into C so it is caught and `failed`
+/// is returned instead
+fn guard<T, F>(failed: T, f: F) -> T
+    where F: FnOnce() -> T
+{
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+fn link(reader: &Reader, entity: u32) -> Link {
+    Link {
+        reader: reader,
+        entity: entity as usize
+    }
+}
+
+/// Open the hairball at `path`, returns null if it could not be read
+#[no_mangle]
+pub unsafe extern fn hairball_open(path: *const c_char) -> *mut Hairball {
+    guard(ptr::null_mut(), || {
+        match string(path).map(Reader::read) {
+            Some(Ok(reader)) => Box::into_raw(Box::new(Hairball { reader: reader })),
+            _ => ptr::null_mut()
+        }
+    })
+}
+
+/// Close a hairball that was opened with `hairball_open`
+#[no_mangle]
+pub unsafe extern fn hairball_close(hb: *mut Hairball) {
+    guard((), || {
+        if !hb.is_null() {
+            drop(Box::from_raw(hb));
+        }
+    })
+}
+
+/// Copy the 16 bytes of the hairball's uuid into `uuid`
+#[no_mangle]
+pub unsafe extern fn hairball_uuid(hb: *const Hairball, uuid: *mut u8) -> bool {
+    guard(false, || {
+        match get(hb) {
+            Some(reader) if !uuid.is_null() => {
+                let file = reader.uuid();
+                ptr::copy_nonoverlapping(file.as_bytes().as_ptr(), uuid, 16);
+                true
+            }
+            _ => false
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern fn hairball_revision(hb: *const Hairball) -> u64 {
+    guard(0, || {
+        get(hb).map(|r| r.revision()).unwrap_or(0)
+    })
+}
+
+/// The number of entities, they are numbered from 0
+#[no_mangle]
+pub unsafe extern fn hairball_entities_len(hb: *const Hairball) -> u32 {
+    guard(0, || {
+        get(hb).map(|r| r.entities_len() as u32).unwrap_or(0)
+    })
+}
+
+/// Read an entity, returns false if it does not exist
+#[no_mangle]
+pub unsafe extern fn hairball_entity(hb: *const Hairball, entity: u32, out: *mut HairballEntity) -> bool {
+    guard(false, || {
+        let e = match get(hb).and_then(|r| r.get_entity(entity as usize)) {
+            Some(e) => e,
+            None => return false
+        };
+        if out.is_null() {
+            return false;
+        }
+
+        let out = &mut *out;
+        let (name, name_len) = e.name().map(|n| (n.as_ptr() as *const c_char, n.len()))
+            .unwrap_or((ptr::null(), 0));
+        out.name = name;
+        out.name_len = name_len as size_t;
+        out.has_parent = e.parent().is_some();
+        out.parent = e.parent().unwrap_or(0);
+        out.external = e.file().is_some();
+        out.file = [0; 16];
+        if let Some(file) = e.file() {
+            copy_uuid(&mut out.file, file.as_bytes());
+        }
+        out.has_guid = e.guid().is_some();
+        out.guid = [0; 16];
+        if let Some(guid) = e.guid() {
+            copy_uuid(&mut out.guid, guid.as_bytes());
+        }
+        true
+    })
+}
+
+/// Write the path of an entity into `buf` like `snprintf`, the path is
+/// truncated and nul terminated if `buf` is too small. Returns the length
+/// of the whole path, 0 if the entity does not have a path.
+#[no_mangle]
+pub unsafe extern fn hairball_entity_path(hb: *const Hairball, entity: u32,
+                                          buf: *mut c_char, len: size_t) -> size_t {
+    guard(0, || {
+        let path = match get(hb).and_then(|r| r.path(entity as usize)) {
+            Some(path) => path,
+            None => return 0
+        };
+        if !buf.is_null() && len > 0 {
+            let n = std::cmp::min(path.len(), len as usize - 1);
+            ptr::copy_nonoverlapping(path.as_ptr() as *const c_char, buf, n);
+            *buf.offset(n as isize) = 0;
+        }
+        path.len() as size_t
+    })
+}
+
+/// Find an entity by it's path
+#[no_mangle]
+pub unsafe extern fn hairball_lookup(hb: *const Hairball, path: *const c_char, entity: *mut u32) -> bool {
+    guard(false, || {
+        match (get(hb), string(path)) {
+            (Some(reader), Some(path)) if !entity.is_null() => {
+                match reader.lookup(path) {
+                    Some(idx) => {
+                        *entity = idx as u32;
+                        true
+                    }
+                    None => false
+                }
+            }
+            _ => false
+        }
+    })
+}
+
+/// The number of columns in the hairball, the tombstones of a column are
+/// not counted
+#[no_mangle]
+pub unsafe extern fn hairball_columns_len(hb: *const Hairball) -> u32 {
+    guard(0, || {
+        get(hb).map(|r| r.column_names().len() as u32).unwrap_or(0)
+    })
+}
+
+/// Get the name of a column, columns are numbered from 0
+#[no_mangle]
+pub unsafe extern fn hairball_column_name(hb: *const Hairball, column: u32,
+                                          name: *mut *const c_char, len: *mut size_t) -> bool {
+    guard(false, || {
+        let n = match get(hb).and_then(|r| r.column_names().get(column as usize).cloned()) {
+            Some(n) => n,
+            None => return false
+        };
+        if name.is_null() || len.is_null() {
+            return false;
+        }
+        *name = n.as_ptr() as *const c_char;
+        *len = n.len() as size_t;
+        true
+    })
+}
+
+/// Check if the hairball has a column with the name
+#[no_mangle]
+pub unsafe extern fn hairball_has_column(hb: *const Hairball, name: *const c_char) -> bool {
+    guard(false, || {
+        match (get(hb), string(name)) {
+            (Some(reader), Some(name)) => reader.column_names().contains(&name),
+            _ => false
+        }
+    })
+}
+
+/// The number of vertex buffers in the mesh of an entity, 0 if the
+/// entity does not have a mesh
+#[no_mangle]
+pub unsafe extern fn hairball_mesh_buffers(hb: *const Hairball, entity: u32) -> u32 {
+    guard(0, || {
+        get(hb).and_then(|r| hairball_mesh::lookup(&link(r, entity)))
+            .map(|m| m.len() as u32)
+            .unwrap_or(0)
+    })
+}
+
+/// Read a vertex buffer of the mesh of an entity
+#[no_mangle]
+pub unsafe extern fn hairball_mesh_buffer(hb: *const Hairball, entity: u32,
+                                          buffer: u32, out: *mut HairballBuffer) -> bool {
+    guard(false, || {
+        let mesh = match get(hb).and_then(|r| hairball_mesh::lookup(&link(r, entity))) {
+            Some(mesh) => mesh,
+            None => return false
+        };
+        match mesh.get(buffer as usize) {
+            Some(vb) if !out.is_null() => {
+                let data: &[u8] = vb.data();
+                *out = HairballBuffer {
+                    data: data.as_ptr(),
+                    data_len: data.len() as size_t,
+                    stride: hairball_mesh::stride(&vb.attributes()[..]) as u32,
+                    attributes: vb.attributes().len() as u32
+                };
+                true
+            }
+            _ => false
+        }
+    })
+}
+
+/// Read an attribute of a vertex buffer of the mesh of an entity
+#[no_mangle]
+pub unsafe extern fn hairball_mesh_attribute(hb: *const Hairball, entity: u32, buffer: u32,
+                                             attribute: u32, out: *mut HairballAttribute) -> bool {
+    guard(false, || {
+        let mesh = match get(hb).and_then(|r| hairball_mesh::lookup(&link(r, entity))) {
+            Some(mesh) => mesh,
+            None => return false
+        };
+        let attributes = match mesh.get(buffer as usize) {
+            Some(vb) => vb.attributes(),
+            None => return false
+        };
+        let idx = attribute as usize;
+        match attributes.get(idx) {
+            Some(a) if !out.is_null() => {
+                let name: &str = a.name;
+                let element_type = hairball_mesh::type_name(a);
+                *out = HairballAttribute {
+                    name: name.as_ptr() as *const c_char,
+                    name_len: name.len() as size_t,
+                    element_type: element_type.as_ptr() as *const c_char,
+                    element_type_len: element_type.len() as size_t,
+                    element_count: a.element_count,
+                    offset: hairball_mesh::stride(&attributes[..idx]) as u32
+                };
+                true
+            }
+            _ => false
+        }
+    })
+}
+
+/// Copy the index list of an entity into `out`, at most `len` indices are
+/// copied. Returns the length of the whole list, 0 if the entity does
+/// not have one.
+#[no_mangle]
+pub unsafe extern fn hairball_mesh_index(hb: *const Hairball, entity: u32,
+                                         out: *mut u32, len: size_t) -> size_t {
+    guard(0, || {
+        let index = match get(hb).and_then(|r| hairball_mesh_index::lookup(&link(r, entity))) {
+            Some(index) => index,
+            None => return 0
+        };
+        if !out.is_null() {
+            let n = std::cmp::min(index.len(), len as usize);
+            ptr::copy_nonoverlapping(index.as_ptr(), out, n);
+        }
+        index.len() as size_t
+    })
+}
+
+/// Read the geometry of an entity
+#[no_mangle]
+pub unsafe extern fn hairball_geometry(hb: *const Hairball, entity: u32, out: *mut HairballGeometry) -> bool {
+    guard(false, || {
+        match get(hb).and_then(|r| hairball_geometry::lookup(&link(r, entity))) {
+            Some(g) if !out.is_null() => {
+                *out = HairballGeometry {
+                    mesh: g.mesh,
+                    start: g.start,
+                    length: g.length
+                };
+                true
+            }
+            _ => false
+        }
+    })
+}
+
+/// Read the draw binding of an entity
+#[no_mangle]
+pub unsafe extern fn hairball_draw_binding(hb: *const Hairball, entity: u32, out: *mut HairballDrawBinding) -> bool {
+    guard(false, || {
+        match get(hb).and_then(|r| hairball_draw_binding::lookup(&link(r, entity))) {
+            Some(b) if !out.is_null() => {
+                *out = HairballDrawBinding {
+                    geometry: b.geometry,
+                    material: b.material
+                };
+                true
+            }
+            _ => false
+        }
+    })
+}
+
+/// Copy the material components of an entity into `out`, at most `len`
+/// are copied. Returns the number of components the entity has.
+#[no_mangle]
+pub unsafe extern fn hairball_materials(hb: *const Hairball, entity: u32,
+                                        out: *mut HairballMaterial, len: size_t) -> size_t {
+    guard(0, || {
+        let material = match get(hb) {
+            Some(reader) => hairball_material::lookup(&link(reader, entity)),
+            None => return 0
+        };
+        if !out.is_null() {
+            for (i, &(c, v)) in material.iter().take(len as usize).enumerate() {
+                let component = match c {
+                    Component::Ambient => 0,
+                    Component::Diffuse => 1,
+                    Component::Specular => 2
+                };
+                *out.offset(i as isize) = match v {
+                    Value::Color(color) => HairballMaterial {
+                        component: component,
+                        kind: 0,
+                        color: color,
+                        texture: 0
+                    },
+                    Value::Texture(texture) => HairballMaterial {
+                        component: component,
+                        kind: 1,
+                        color: [0.; 4],
+                        texture: texture
+                    }
+                };
+            }
+        }
+        material.len() as size_t
+    })
+}
@@ -1,4 +1,5 @@
 extern crate capnp;
+#[macro_use]
 extern crate hairball;
 
 #[cfg(feature="build-schema")]
@@ -9,83 +10,84 @@ pub mod geometry_capnp {
 #[cfg(not(feature="build-schema"))]
 pub mod geometry_capnp;
 
-const COLUMN_NAME: &'static str = "geometry";
+/// The name the column is stored under in a hairball
+pub const COLUMN_NAME: &'static str = "geometry";
 
-#[derive(Copy, Debug, Clone)]
-pub struct Geometry<E> {
-    pub mesh: E,
-    pub start: u32,
-    pub length: u32
+/// The schema of the column as a serialized CodeGeneratorRequest, this is
+/// only available if the crate was built with `build-schema`
+#[cfg(feature="build-schema")]
+pub fn schema() -> Option<&'static [u8]> {
+    Some(include_bytes!(concat!(env!("OUT_DIR"), "/geometry.schema")))
 }
 
-/// Write out a column into a hairball
-pub fn write(hb: &mut hairball::Builder, i: &[(u32, Geometry<u32>)]) {
-    let column: geometry_capnp::column::Builder = hb.column(COLUMN_NAME)
-        .and_then(|c| c.get_as())
-        .unwrap();
-
-    let mut rows = column.init_geometries(i.len() as u32);
-    for (i, &(name, geo)) in i.iter().enumerate() {
-        let mut row = rows.borrow().get(i as u32);
-        row.set_id(name);
-        row.set_start(geo.start);
-        row.set_length(geo.length);
-        row.set_mesh(geo.mesh);
-    }
+#[cfg(not(feature="build-schema"))]
+pub fn schema() -> Option<&'static [u8]> {
+    None
 }
 
-
-impl<'a, E> Iterator for Reader<'a, E> {
-    type Item = (&'a E, Geometry<&'a E>);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        for i in &mut self.index {
-            let row = self.column.get(i);
-
-            let id = match self.reader.entity(row.get_id() as usize) {
-                Some(id) => id,
-                None => continue
-            };
-
-            let mesh = match self.reader.entity(row.get_mesh() as usize) {
-                Some(mesh) => mesh,
-                None => continue
-            };
-
-            return Some((
-                id,
-                Geometry{
-                    mesh: mesh,
-                    start: row.get_start(),
-                    length: row.get_length()
-                }
-            ));
+entity_column! {
+    geometry_capnp::column.get_geometries/init_geometries[geometry],
+    #[derive(Copy, Debug, Clone)]
+    pub struct Geometry {
+        entities { mesh: get_mesh / set_mesh }
+        values {
+            start: u32 = get_start / set_start,
+            length: u32 = get_length / set_length
         }
-        None
     }
 }
 
-/// Used to read geometry colimn from the hairball
-pub struct Reader<'a, E:'a> {
-    reader: &'a hairball::ReaderMapping<'a, E>,
-    column: capnp::struct_list::Reader<'a, geometry_capnp::geometry::Owned>,
-    index: std::ops::Range<u32>
+/// Read the column from every layer of an overlay, the rows of an entity
+/// in a later layer replace it's rows in earlier layers
+pub fn read_overlay<'a, E>(overlay: &'a hairball::OverlayMapping<'a, E>)
+    -> Option<Box<Iterator<Item=(&'a E, Geometry<&'a E>)> + 'a>>
+    where E: 'a
+{
+    overlay.column(COLUMN_NAME, |layer| {
+        read(layer).map(|rows| {
+            Box::new(rows.map(|(&id, g)| {
+                (id, Geometry {
+                    mesh: *g.mesh,
+                    start: g.start,
+                    length: g.length
+                })
+            })) as Box<Iterator<Item=(usize, Geometry<usize>)> + 'a>
+        })
+    }).map(|rows| {
+        Box::new(rows.filter_map(move |(id, g)| {
+            overlay.entity(g.mesh).map(|mesh| {
+                (id, Geometry {
+                    mesh: mesh,
+                    start: g.start,
+                    length: g.length
+                })
+            })
+        })) as Box<Iterator<Item=(&'a E, Geometry<&'a E>)> + 'a>
+    })
 }
 
-/// Create a column reader for iff the hairball has a valid geometry
-/// column set
-pub fn read<'a, E>(read: &'a hairball::ReaderMapping<'a, E>) -> Option<Reader<'a, E>>
-    where E: 'a
+/// Read the geometry column of `reader`, the mesh of each geometry is
+/// resolved through the library so it may be in another hairball. Rows
+/// whose mesh could not be resolved are skipped.
+pub fn read_linked<'a>(library: &'a hairball::Library, reader: &'a hairball::Reader)
+    -> Option<Box<Iterator<Item=(u32, Geometry<hairball::Link<'a>>)> + 'a>>
 {
-    read.column(COLUMN_NAME)
-        .and_then(|c| c.get_as().ok() )
-        .and_then(|c: geometry_capnp::column::Reader<'a>| c.get_geometries().ok( ))
-        .map(|c| {
-            let len = c.len();
-            Reader{
-                reader: read,
-                column: c,
-                index: (0..len)
-            }
+    let chunks: Vec<_> = reader.chunks(COLUMN_NAME).into_iter()
+        .filter_map(|c| c.data.get_as().ok())
+        .filter_map(|c: geometry_capnp::column::Reader<'a>| c.get_geometries().ok())
+        .collect();
+    if chunks.len() == 0 {
+        return None;
+    }
+
+    let rows = chunks.into_iter().flat_map(|rows| (0..rows.len()).map(move |i| rows.get(i)));
+    Some(Box::new(rows.filter_map(move |row| {
+        library.resolve(reader, row.get_mesh()).map(|mesh| {
+            (row.get_id(), Geometry {
+                mesh: mesh,
+                start: row.get_start(),
+                length: row.get_length()
+            })
         })
-}
\ No newline at end of file
+    })) as Box<Iterator<Item=(u32, Geometry<hairball::Link<'a>>)> + 'a>)
+}
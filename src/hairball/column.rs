@@ -0,0 +1,368 @@
+//! Macros that generate the code shared by every column crate. A column
+//! is a capnp struct with a list of rows, each row has an `id` that is
+//! the entity it belongs to. The crate using these macros must declare
+//! `COLUMN_NAME`, a `schema` function that returns the column's embedded
+//! schema and have `capnp` as a dependency.
+
+use std::fmt;
+
+use capnp;
+
+/// Why a row of a column could not be read
+#[derive(Debug)]
+pub enum RowCause {
+    /// The row could not be decoded
+    Capnp(capnp::Error),
+    /// An enum in the row has a value that is not in the schema
+    NotInSchema(u16),
+    /// The row references an entity that does not exist
+    EntityOutOfRange(u32),
+    /// The row was decoded but it's contents are not valid
+    Invalid(String)
+}
+
+impl From<capnp::Error> for RowCause {
+    fn from(err: capnp::Error) -> RowCause {
+        RowCause::Capnp(err)
+    }
+}
+
+impl From<capnp::NotInSchema> for RowCause {
+    fn from(err: capnp::NotInSchema) -> RowCause {
+        RowCause::NotInSchema(err.0)
+    }
+}
+
+/// A row that a strict column reader could not read
+#[derive(Debug)]
+pub struct RowError {
+    pub column: &'static str,
+    pub row: u32,
+    pub cause: RowCause
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} row {}: ", self.column, self.row));
+        match self.cause {
+            RowCause::Capnp(ref err) => write!(f, "{:?}", err),
+            RowCause::NotInSchema(value) => write!(f, "enum value {} is not in the schema", value),
+            RowCause::EntityOutOfRange(entity) => write!(f, "entity {} does not exist", entity),
+            RowCause::Invalid(ref msg) => write!(f, "{}", msg)
+        }
+    }
+}
+
+/// Generate the `Reader` of a column, the `read` function that creates it
+/// from a `ReaderMapping` and a `validate` function. The `Reader` can find
+/// the rows of a single entity using `get`.
+///
+/// ```ignore
+/// column_reader! {
+///     index_capnp::column.get_meshes[index],
+///     |mapping, row| -> (&'a E, Vec<u32>) => {
+///         let id = match try!(mapping.row_entity(row.get_id())) {
+///             Some(id) => id,
+///             None => return Ok(None)
+///         };
+///         Ok(Some((id, try!(decode(row)))))
+///     },
+///     references |row| [row.get_id()]
+/// }
+/// ```
+///
+/// The block is called for every row and returns the item the `Reader`
+/// yields, None if the entity is not mapped or a `RowCause` if the row
+/// is bad. The `Reader` skips bad rows, the `Strict` reader yields them
+/// as errors. `references` lists every entity id in a row, these are
+/// checked by `validate`.
+#[macro_export]
+macro_rules! column_reader {
+    (
+        $module:ident :: $column:ident . $rows:ident [$row:ident],
+        |$mapping:ident, $r:ident| -> $item:ty => $decode:block,
+        references |$rr:ident| $refs:expr
+    ) => {
+        /// Used to read the column from a hairball
+        pub struct Reader<'a, E: 'a> {
+            reader: &'a $crate::ReaderMapping<'a, E>,
+            // every chunk of the column, with the number of rows in the
+            // chunks before it and if it is sorted
+            chunks: Vec<(u32, bool, ::capnp::struct_list::Reader<'a, $module::$row::Owned>)>,
+            chunk: usize,
+            index: ::std::ops::Range<u32>,
+            // only rows of this entity are read
+            entity: Option<u32>
+        }
+
+        impl<'a, E> Reader<'a, E> {
+            /// Get the rows that belong to the entity `idx`. This is a
+            /// binary search of each chunk whose rows were sorted when
+            /// they were written, other chunks are read in full.
+            pub fn get(&self, idx: u32) -> Reader<'a, E> {
+                let mut reader = Reader {
+                    reader: self.reader,
+                    chunks: self.chunks.iter().map(|&c| c).collect(),
+                    chunk: 0,
+                    index: (0..0),
+                    entity: Some(idx)
+                };
+                reader.index = reader.rows(0);
+                reader
+            }
+
+            /// true if the rows of every chunk are sorted by entity
+            pub fn sorted(&self) -> bool {
+                self.chunks.iter().all(|&(_, sorted, _)| sorted)
+            }
+
+            // the rows of a chunk that may belong to the entity
+            fn rows(&self, chunk: usize) -> ::std::ops::Range<u32> {
+                match (self.chunks.get(chunk), self.entity) {
+                    (Some(&(_, true, column)), Some(entity)) => {
+                        $crate::sorted_rows(column.len(), entity, |i| column.get(i).get_id())
+                    }
+                    (Some(&(_, _, column)), _) => (0..column.len()),
+                    (None, _) => (0..0)
+                }
+            }
+        }
+
+        fn decode_row<'a, E>($mapping: &'a $crate::ReaderMapping<'a, E>,
+                             $r: $module::$row::Reader<'a>)
+            -> Result<Option<$item>, $crate::RowCause>
+            $decode
+
+        impl<'a, E> Reader<'a, E> {
+            /// Read the rows as `Result`s, rows that would be skipped
+            /// because they are bad are returned as errors
+            pub fn strict(self) -> Strict<'a, E> {
+                Strict {
+                    reader: self
+                }
+            }
+
+            fn next_row(&mut self) -> Option<Result<$item, $crate::RowError>> {
+                while let Some(&(offset, _, column)) = self.chunks.get(self.chunk) {
+                    for i in &mut self.index {
+                        let row = column.get(i);
+                        if self.entity.map(|e| e != row.get_id()).unwrap_or(false) {
+                            continue;
+                        }
+                        match decode_row(self.reader, row) {
+                            Ok(Some(item)) => return Some(Ok(item)),
+                            Ok(None) => (),
+                            Err(cause) => return Some(Err($crate::RowError {
+                                column: COLUMN_NAME,
+                                row: offset + i,
+                                cause: cause
+                            }))
+                        }
+                    }
+                    self.chunk += 1;
+                    self.index = self.rows(self.chunk);
+                }
+                None
+            }
+        }
+
+        impl<'a, E> Iterator for Reader<'a, E> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<$item> {
+                while let Some(row) = self.next_row() {
+                    if let Ok(item) = row {
+                        return Some(item);
+                    }
+                }
+                None
+            }
+        }
+
+        /// A column reader that reports bad rows, see `Reader::strict`
+        pub struct Strict<'a, E: 'a> {
+            reader: Reader<'a, E>
+        }
+
+        impl<'a, E> Iterator for Strict<'a, E> {
+            type Item = Result<$item, $crate::RowError>;
+
+            fn next(&mut self) -> Option<Result<$item, $crate::RowError>> {
+                self.reader.next_row()
+            }
+        }
+
+        /// Create a column reader iff the hairball has a valid column, the
+        /// rows of every chunk of the column are read
+        pub fn read<'a, E>(read: &'a $crate::ReaderMapping<'a, E>) -> Option<Reader<'a, E>>
+            where E: 'a
+        {
+            let mut chunks = Vec::new();
+            let mut offset = 0;
+            for chunk in read.chunks(COLUMN_NAME) {
+                let rows = chunk.data.get_as::<$module::$column::Reader<'a>>().ok()
+                    .and_then(|c| c.$rows().ok());
+                if let Some(rows) = rows {
+                    chunks.push((offset, chunk.sorted, rows));
+                    offset += rows.len();
+                }
+            }
+            if chunks.len() == 0 {
+                return None;
+            }
+
+            let mut reader = Reader {
+                reader: read,
+                chunks: chunks,
+                chunk: 0,
+                index: (0..0),
+                entity: None
+            };
+            reader.index = reader.rows(0);
+            Some(reader)
+        }
+
+        /// Check that every row of the column only references entities that
+        /// exist in `reader`
+        pub fn validate(reader: &$crate::Reader) -> Vec<$crate::Invalid> {
+            let len = reader.entities_len() as u32;
+            let mut invalid = Vec::new();
+            let mut offset = 0;
+            for chunk in reader.chunks(COLUMN_NAME) {
+                let rows = chunk.data.get_as::<$module::$column::Reader>().ok()
+                    .and_then(|c| c.$rows().ok());
+                let rows = match rows {
+                    Some(rows) => rows,
+                    None => continue
+                };
+                for i in 0..rows.len() {
+                    let $rr = rows.get(i);
+                    for &entity in &$refs {
+                        if entity >= len {
+                            invalid.push($crate::Invalid::DanglingReference {
+                                column: COLUMN_NAME.to_owned(),
+                                row: offset + i,
+                                entity: entity
+                            });
+                        }
+                    }
+                }
+                offset += rows.len();
+            }
+            invalid
+        }
+    }
+}
+
+/// Generate a column whose rows are a struct of entity references and
+/// plain values, the values must be `Copy`. This creates the struct, the
+/// `write` and `lookup` functions and everything `column_reader!` does.
+/// The rows are written sorted by entity, each call to `write` appends a
+/// chunk to the column.
+///
+/// ```ignore
+/// entity_column! {
+///     geometry_capnp::column.get_geometries/init_geometries[geometry],
+///     #[derive(Copy, Debug, Clone)]
+///     pub struct Geometry {
+///         entities { mesh: get_mesh / set_mesh }
+///         values { start: u32 = get_start / set_start }
+///     }
+/// }
+/// ```
+///
+/// The struct is generic over the entity type, the entities are declared
+/// before the values.
+#[macro_export]
+macro_rules! entity_column {
+    (
+        $module:ident :: $column:ident . $rows:ident / $init_rows:ident [$row:ident],
+        $(#[$attr:meta])*
+        pub struct $ty:ident {
+            entities { $($e:ident : $e_get:ident / $e_set:ident),+ }
+            values { $($v:ident : $vty:ty = $v_get:ident / $v_set:ident),* }
+        }
+    ) => {
+        $(#[$attr])*
+        pub struct $ty<E> {
+            $(pub $e: E,)+
+            $(pub $v: $vty,)*
+        }
+
+        /// Append rows to the column of a hairball, rows written by earlier
+        /// calls are kept
+        pub fn write<C>(hb: &mut C, i: &[(u32, $ty<u32>)])
+            where C: $crate::Columns
+        {
+            let order = $crate::sorted_order(i, |r| r.0);
+            {
+                let column: $module::$column::Builder = hb.append_rows(COLUMN_NAME, i.len() as u32)
+                    .and_then(|c| c.get_as())
+                    .unwrap();
+
+                let mut rows = column.$init_rows(i.len() as u32);
+                for (n, &idx) in order.iter().enumerate() {
+                    let &(id, ref r) = &i[idx];
+                    let mut row = rows.borrow().get(n as u32);
+                    row.set_id(id);
+                    $(row.$e_set(r.$e);)+
+                    $(row.$v_set(r.$v);)*
+                }
+            }
+            for (row, &idx) in order.iter().enumerate() {
+                let &(id, ref r) = &i[idx];
+                hb.reference(COLUMN_NAME, row as u32, id);
+                $(hb.reference(COLUMN_NAME, row as u32, r.$e);)+
+            }
+            hb.set_sorted(COLUMN_NAME, true);
+            hb.set_schema(COLUMN_NAME,
+                          <$module::$column::Reader as ::capnp::traits::HasTypeId>::type_id(),
+                          schema());
+        }
+
+        column_reader! {
+            $module::$column.$rows[$row],
+            |mapping, row| -> (&'a E, $ty<&'a E>) => {
+                let id = match try!(mapping.row_entity(row.get_id())) {
+                    Some(id) => id,
+                    None => return Ok(None)
+                };
+                Ok(Some((id, $ty {
+                    $($e: match try!(mapping.row_entity(row.$e_get())) {
+                        Some(e) => e,
+                        None => return Ok(None)
+                    },)+
+                    $($v: row.$v_get(),)*
+                })))
+            },
+            references |row| [row.get_id() $(, row.$e_get())+]
+        }
+
+        /// Find the row of an entity that was resolved through a `Library`,
+        /// the entity references are ids inside of `link.reader`. Sorted
+        /// chunks are binary searched.
+        pub fn lookup(link: &$crate::Link) -> Option<$ty<u32>> {
+            for chunk in link.reader.chunks(COLUMN_NAME) {
+                let rows = chunk.data.get_as::<$module::$column::Reader>().ok()
+                    .and_then(|c| c.$rows().ok());
+                if let Some(rows) = rows {
+                    let range = if chunk.sorted {
+                        $crate::sorted_rows(rows.len(), link.entity as u32, |i| rows.get(i).get_id())
+                    } else {
+                        0..rows.len()
+                    };
+                    for i in range {
+                        let row = rows.get(i);
+                        if row.get_id() as usize == link.entity {
+                            return Some($ty {
+                                $($e: row.$e_get(),)+
+                                $($v: row.$v_get(),)*
+                            });
+                        }
+                    }
+                }
+            }
+            None
+        }
+    }
+}
@@ -11,13 +11,22 @@
 //!
 //! A segment header is pretty simple
 //! [allocated in words; u32][reserved; u32]
+//!
+//! If the `FLAG_MESSAGE_TABLE` flag is set the segment table is followed
+//! by a message table. Each capnp message in the container lists the
+//! segments it is made of, the first message is the root of the hairball.
+//!
+//! [num messages; u32]([num segments; u32][segment id; u32]...)...
 
 
 use std;
 use std::io::Read;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
 use memmap::{Mmap, Protection};
 use capnp;
 use uuid;
+use Error;
 
 use byteorder::{self, ReadBytesExt, WriteBytesExt, LittleEndian};
 
@@ -26,23 +35,34 @@ const CONTAINER_HEADER_SIZE: u64 = 8 + 3 * 4 + 4 + 4 + 4 + 8 + 16;
 const DEFAULT_OFFSET: u64 = 4096;
 const ALLOC_SIZE: u32 = 4096;
 
+/// Set if the segment table is followed by a message table
+const FLAG_MESSAGE_TABLE: u32 = 1;
+
 pub struct Container {
     file: std::fs::File,
+    path: std::path::PathBuf,
     segments: Vec<Segment>,
-    uuid: uuid::Uuid
-}
-
-#[derive(Debug)]
-pub enum Error {
-    // The header of the file is invalid
-    InvalidHeader,
-    Io(std::io::Error),
+    // The segments that make up each message, message 0 is
+    // the root of the hairball
+    messages: Vec<Vec<u32>>,
+    uuid: uuid::Uuid,
+    // The library version that wrote the file
+    version: [u32; 3],
+    // Segments of removed messages that can be given to a new message
+    free: Vec<u32>,
+    writable: bool
 }
 
-impl std::convert::From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Error {
-        Error::Io(err)
-    }
+/// Where a segment of a container is stored in the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentInfo {
+    /// The message the segment belongs to, message 0 is the root. This
+    /// is None if the segment was freed and never reused.
+    pub message: Option<u32>,
+    /// The offset of the segment from the start of the file in bytes
+    pub offset: u64,
+    /// The size of the segment in bytes
+    pub size: u64
 }
 
 impl std::convert::From<byteorder::Error> for Error {
@@ -123,9 +143,15 @@ impl Container {
     fn write_header(&mut self) -> Result<(), Error> {
         use std::io::{Write, Seek, SeekFrom};
 
+        // The message table is written directly after the segment table
+        let table_size = 4 * self.segments.len() as u64 + 4 +
+            self.messages.iter()
+                .map(|m| 4 + 4 * m.len() as u64)
+                .fold(0, |a, b| a + b);
+
         // We can place the segment table at the start of the file
         // otherwise it gets placed after the last segment
-        let offset = if (DEFAULT_OFFSET - CONTAINER_HEADER_SIZE) / 4 > self.segments.len() as u64 {
+        let offset = if DEFAULT_OFFSET - CONTAINER_HEADER_SIZE > table_size {
             CONTAINER_HEADER_SIZE
         } else {
             self.segments[self.segments.len()-1].next_offset()
@@ -137,6 +163,15 @@ impl Container {
             try!(self.file.write_u32::<LittleEndian>(s.size as u32))
         }
 
+        // Write out the message table
+        try!(self.file.write_u32::<LittleEndian>(self.messages.len() as u32));
+        for m in &self.messages {
+            try!(self.file.write_u32::<LittleEndian>(m.len() as u32));
+            for &id in m {
+                try!(self.file.write_u32::<LittleEndian>(id));
+            }
+        }
+
         // turn the uuid in a byte array
         let mut uuid = [0; 16];
         for (i, b) in self.uuid.as_bytes().iter().enumerate() {
@@ -149,15 +184,11 @@ impl Container {
             self.segments[0].offset as u32
         };
 
-        let version = ::semver::Version::parse(::VERSION).unwrap();
-
         try!(self.file.seek(SeekFrom::Start(0)));
         Header{
             offset: first,
-            version: [version.major as u32,
-                      version.minor as u32,
-                      version.patch as u32],
-            flags: 0,
+            version: self.version,
+            flags: FLAG_MESSAGE_TABLE,
             num_segments: self.segments.len() as u32,
             segments_offset: offset,
             uuid: uuid
@@ -170,6 +201,7 @@ impl Container {
     {
         use std::io::{Seek, SeekFrom};
 
+        let path = p.as_ref().to_path_buf();
         let mut f = try!(std::fs::File::open(p));
 
         let header = try!(Header::read(&mut f));
@@ -183,11 +215,34 @@ impl Container {
             );
         }
 
-        // Get the current offset
+        // Older containers hold a single message made up of
+        // every segment in the file
+        let messages = if header.flags & FLAG_MESSAGE_TABLE != 0 {
+            let count = try!(f.read_u32::<LittleEndian>());
+            let mut messages = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let len = try!(f.read_u32::<LittleEndian>());
+                let mut ids = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    let id = try!(f.read_u32::<LittleEndian>());
+                    if id >= header.num_segments {
+                        return Err(Error::InvalidHeader);
+                    }
+                    ids.push(id);
+                }
+                messages.push(ids);
+            }
+            messages
+        } else {
+            vec![(0..header.num_segments).collect()]
+        };
+
+        // Get the current offset, segments are not mapped until
+        // they are first requested
         let mut offset = header.offset as u64;
         let mut segments = Vec::with_capacity(segment_table.len());
         for size in segment_table {
-            let s = try!(Segment::read(&mut f, offset, size));
+            let s = Segment::unmapped(offset, size);
             offset = s.next_offset();
             segments.push(s);
         }
@@ -195,8 +250,13 @@ impl Container {
 
         Ok(Container {
             uuid: uuid::Uuid::from_bytes(&header.uuid[..]).unwrap(),
+            version: header.version,
             file: f,
+            path: path,
             segments: segments,
+            messages: messages,
+            free: Vec::new(),
+            writable: false
         })
     }
 
@@ -204,6 +264,7 @@ impl Container {
     fn create<P>(p: P, uuid: uuid::Uuid) -> Result<Container, Error>
         where P: AsRef<std::path::Path>
     {
+        let path = p.as_ref().to_path_buf();
         let file = std::fs::OpenOptions::new()
             .read(true)
             .write(true)
@@ -211,45 +272,269 @@ impl Container {
             .truncate(true)
             .open(p);
 
+        let version = ::semver::Version::parse(::VERSION).unwrap();
         let mut c = Container {
             uuid: uuid,
+            version: [version.major as u32,
+                      version.minor as u32,
+                      version.patch as u32],
             file: try!(file),
+            path: path,
             segments: Vec::new(),
+            messages: Vec::new(),
+            free: Vec::new(),
+            writable: true
         };
 
         try!(c.write_header());
         Ok(c)
     }
 
+    /// Add a new segment to the end of the file and append it to `message`
+    /// The smallest segment that is larger than `size` is reused if one
+    /// was freed, it is zeroed before it is handed out.
+    fn allocate(&mut self, message: u32, size: u32) -> Result<&Segment, Error> {
+        let mut best: Option<usize> = None;
+        for (i, &id) in self.free.iter().enumerate() {
+            let len = self.segments[id as usize].size;
+            let smaller = best.map(|b| len < self.segments[self.free[b] as usize].size);
+            if len >= size as usize && smaller.unwrap_or(true) {
+                best = Some(i);
+            }
+        }
+        if let Some(i) = best {
+            let id = self.free.swap_remove(i);
+            {
+                let seg = &self.segments[id as usize];
+                unsafe {
+                    std::ptr::write_bytes(seg.as_ptr(), 0, seg.size / 8);
+                }
+            }
+            self.messages[message as usize].push(id);
+            return Ok(&self.segments[id as usize]);
+        }
+
+        let offset = if self.segments.len() == 0 {
+            DEFAULT_OFFSET
+        } else {
+            let len = self.segments.len();
+            self.segments[len-1].next_offset()
+        };
+
+        let segment = try!(Segment::create(&mut self.file, offset, size));
+        self.segments.push(segment);
+        let id = self.segments.len() as u32 - 1;
+        self.messages[message as usize].push(id);
+        Ok(&self.segments[id as usize])
+    }
+
+    /// Release the segments of a message so `allocate` can reuse them,
+    /// the message is left empty. Nothing may point into the segments.
+    fn free_message(&mut self, message: u32) {
+        if let Some(ids) = self.messages.get_mut(message as usize) {
+            self.free.extend(ids.drain(..));
+        }
+    }
+
+    /// Write the header, once it is written the header is not written
+    /// again when the container is dropped
+    fn close(&mut self) -> Result<(), Error> {
+        if self.writable {
+            self.writable = false;
+            try!(self.write_header());
+        }
+        Ok(())
+    }
+
+    /// Give up on a container that is being written, the header is never
+    /// written and the file is removed
+    fn abandon(&mut self) {
+        if self.writable {
+            self.writable = false;
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    /// Get the number of messages in the container
+    pub fn messages_len(&self) -> usize {
+        self.messages.len()
+    }
+
     /// get the uuid of the container
     pub fn uuid(&self) -> uuid::Uuid { self.uuid }
+
+    /// get the version of the library that wrote the container
+    pub fn version(&self) -> [u32; 3] { self.version }
+
+    /// List every segment of the container in the order they are
+    /// stored in the file
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        let mut owner = vec![None; self.segments.len()];
+        for (message, ids) in self.messages.iter().enumerate() {
+            for &id in ids {
+                owner[id as usize] = Some(message as u32);
+            }
+        }
+        self.segments.iter().zip(owner.into_iter())
+            .map(|(s, message)| {
+                SegmentInfo {
+                    message: message,
+                    offset: s.offset,
+                    size: s.size as u64
+                }
+            })
+            .collect()
+    }
+
+    /// Hint to the OS that the segment will be read soon, this will
+    /// map the segment if it has not been mapped yet
+    pub fn prefetch(&self, id: u32) {
+        if let Some(seg) = self.segments.get(id as usize) {
+            if let Ok(words) = seg.words(&self.file) {
+                advice::will_need(words);
+            }
+        }
+    }
+
+    /// Prefetch every segment that makes up a message
+    pub fn prefetch_message(&self, message: u32) {
+        if let Some(ids) = self.messages.get(message as usize) {
+            for &id in ids {
+                self.prefetch(id);
+            }
+        }
+    }
+
+    /// Hash the contents of every message in the container, this
+    /// reads every segment of the file
+    pub fn hash<H>(&self, state: &mut H) -> Result<(), Error>
+        where H: std::hash::Hasher
+    {
+        for ids in &self.messages {
+            for &id in ids {
+                let words = try!(self.segments[id as usize].words(&self.file));
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 8)
+                };
+                state.write(bytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Look up a segment by the id it is known by inside of a message
+    fn message_segment(&self, message: u32, id: u32) -> Option<&[capnp::Word]> {
+        self.messages.get(message as usize)
+            .and_then(|ids| ids.get(id as usize))
+            .and_then(|&id| self.segments.get(id as usize))
+            .and_then(|seg| seg.words(&self.file).ok())
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        // the container was not closed, there is no one left to report
+        // a failure to
+        let _ = self.close();
+    }
+}
+
+/// A shared handle to a single message in a `Container`, this is what
+/// a capnp message reads it's segments from
+#[derive(Clone)]
+pub struct Segments {
+    container: Arc<Container>,
+    message: u32
+}
+
+impl Segments {
+    pub fn new(container: Arc<Container>, message: u32) -> Segments {
+        Segments {
+            container: container,
+            message: message
+        }
+    }
 }
 
-impl capnp::message::ReaderSegments for Container {
+impl capnp::message::ReaderSegments for Segments {
     fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [capnp::Word]> {
-        let out = self.segments.get(id as usize)
-            .map(|seg| seg.words());
-        out
+        self.container.message_segment(self.message, id)
+    }
+}
+
+/// Wraps a message of a `Container` and records the id of every segment
+/// capnp asks for. This is used to find out which segments back a column.
+pub struct Recorder<'a> {
+    container: &'a Container,
+    message: u32,
+    touched: &'a RefCell<Vec<u32>>
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(container: &'a Container, message: u32, touched: &'a RefCell<Vec<u32>>) -> Recorder<'a> {
+        Recorder {
+            container: container,
+            message: message,
+            touched: touched
+        }
     }
 }
 
+impl<'a> capnp::message::ReaderSegments for Recorder<'a> {
+    fn get_segment<'b>(&'b self, id: u32) -> Option<&'b [capnp::Word]> {
+        // record the id the segment is known by in the container
+        if let Some(&id) = self.container.messages.get(self.message as usize)
+                                                  .and_then(|ids| ids.get(id as usize)) {
+            self.touched.borrow_mut().push(id);
+        }
+        self.container.message_segment(self.message, id)
+    }
+}
+
+#[cfg(unix)]
+mod advice {
+    use capnp;
+    use libc::{c_int, c_void, size_t};
+
+    const MADV_WILLNEED: c_int = 3;
+
+    extern {
+        fn madvise(addr: *mut c_void, len: size_t, advice: c_int) -> c_int;
+    }
+
+    /// This is only a hint, so failures are ignored
+    pub fn will_need(words: &[capnp::Word]) {
+        unsafe {
+            madvise(words.as_ptr() as *mut c_void,
+                    (words.len() * 8) as size_t,
+                    MADV_WILLNEED);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod advice {
+    use capnp;
+
+    pub fn will_need(_: &[capnp::Word]) {}
+}
+
 struct Segment {
     offset: u64,
     size: usize,
-    map: Mmap 
+    // The segment is mapped the first time it is accessed, once
+    // mapped it is never unmapped until the container is dropped
+    map: Mutex<Option<Mmap>>
 }
 
 impl Segment {
-    /// Read a segment from a file at a give offset
-    fn read(f: &mut std::fs::File, offset: u64, size: u32) -> Result<Segment, Error> {
-        // Memory map the file in RO mode
-        let map = try!(Mmap::open_with_offset(f, Protection::Read, offset as usize, size as usize));
-
-        Ok(Segment {
+    /// Create a segment at a given offset without mapping it
+    fn unmapped(offset: u64, size: u32) -> Segment {
+        Segment {
             offset: offset,
             size: size as usize,
-            map: map
-        })
+            map: Mutex::new(None)
+        }
     }
 
     /// Creates a new segment for writing
@@ -268,7 +553,7 @@ impl Segment {
         Ok(Segment {
             offset: offset,
             size: size as usize,
-            map: map
+            map: Mutex::new(Some(map))
         })
     }
 
@@ -278,53 +563,116 @@ impl Segment {
     }
 
 
-    /// Get the segment as Capn'Protp words
-    fn words(&self) -> &[capnp::Word] {
-        let ptr = self.as_ptr();
+    /// Get the segment as Capn'Protp words, the segment is mapped
+    /// in RO mode if it has not been mapped yet
+    fn words(&self, f: &std::fs::File) -> Result<&[capnp::Word], Error> {
+        let ptr = try!(self.map(f));
         let len = self.size / 8;
 
         unsafe {
-            std::slice::from_raw_parts(ptr, len)
+            Ok(std::slice::from_raw_parts(ptr, len))
+        }
+    }
+
+    fn map(&self, f: &std::fs::File) -> Result<*mut capnp::Word, Error> {
+        let mut map = self.map.lock().unwrap();
+        if map.is_none() {
+            *map = Some(try!(Mmap::open_with_offset(f, Protection::Read, self.offset as usize, self.size)));
         }
+        Ok(map.as_ref().unwrap().ptr() as *mut capnp::Word)
     }
 
     fn as_ptr(&self) -> *mut capnp::Word {
-        self.map.ptr() as *mut capnp::Word
+        self.map.lock().unwrap()
+            .as_ref()
+            .expect("segment was not mapped")
+            .ptr() as *mut capnp::Word
     }
 }
 
-pub struct Builder(Container);
+/// A shared handle to a container that is being written. The header
+/// is written by `close`, or once the last handle or `Builder` is dropped.
+#[derive(Clone)]
+pub struct Writer(Arc<Mutex<Container>>);
 
-impl Builder {
-    pub fn new<P>(p: P, uuid: uuid::Uuid) -> Result<Builder, Error>
+impl Writer {
+    pub fn new<P>(p: P, uuid: uuid::Uuid) -> Result<Writer, Error>
         where P: AsRef<std::path::Path>
     {
         let c = try!(Container::create(p, uuid));
-        Ok(Builder(c))
+        Ok(Writer(Arc::new(Mutex::new(c))))
     }
-}
 
-impl capnp::message::ReaderSegments for Builder {
-    fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [capnp::Word]> {
-        self.0.segments.get(id as usize).map(|seg| seg.words())
+    /// Create a new message in the container, the first message
+    /// created is the root of the hairball
+    pub fn message(&self) -> Builder {
+        let message = {
+            let mut c = self.0.lock().unwrap();
+            c.messages.push(Vec::new());
+            c.messages.len() as u32 - 1
+        };
+
+        Builder {
+            container: self.0.clone(),
+            message: message
+        }
+    }
+
+    /// Copy every segment of a message from another container into a
+    /// new message. The segments are copied byte for byte, so the
+    /// message does not need to be decoded. Returns the new message id.
+    pub fn copy_message(&self, src: &Container, message: u32) -> Result<u32, Error> {
+        let ids = match src.messages.get(message as usize) {
+            Some(ids) => ids,
+            None => return Err(Error::InvalidHeader)
+        };
+
+        let dst = self.message().id();
+        let mut c = self.0.lock().unwrap();
+        for &id in ids {
+            let seg = &src.segments[id as usize];
+            let words = try!(seg.words(&src.file));
+            let new = try!(c.allocate(dst, seg.size as u32));
+            unsafe {
+                std::ptr::copy_nonoverlapping(words.as_ptr(), new.as_ptr(), words.len());
+            }
+        }
+        Ok(dst)
+    }
+
+    /// Free the segments of a message so they can be reused by the next
+    /// message that is written. Every builder of the message must have
+    /// been dropped, the message id stays valid but is empty.
+    pub fn free_message(&self, message: u32) {
+        self.0.lock().unwrap().free_message(message);
+    }
+
+    /// Write the header of the container
+    pub fn close(&self) -> Result<(), Error> {
+        self.0.lock().unwrap().close()
+    }
+
+    /// Remove the file without writing the header, see `Container::abandon`
+    pub fn abandon(&self) {
+        self.0.lock().unwrap().abandon();
     }
 }
 
-impl Drop for Builder {
-    fn drop(&mut self) {
-        self.0.write_header().unwrap();
+/// A `Builder` allocates the segments for a single message
+pub struct Builder {
+    container: Arc<Mutex<Container>>,
+    message: u32
+}
+
+impl Builder {
+    /// The id of the message in the container
+    pub fn id(&self) -> u32 {
+        self.message
     }
 }
 
 unsafe impl capnp::message::Allocator for Builder {
     fn allocate_segment(&mut self, size: u32) -> (*mut capnp::Word, u32) {
-        let offset = if self.0.segments.len() == 0 {
-            DEFAULT_OFFSET
-        } else {
-            let len = self.0.segments.len();
-            self.0.segments[len-1].next_offset()
-        };
-
         let size = size * 8;
 
         // size must be at least ALLOC_SIZE and must also
@@ -337,10 +685,10 @@ unsafe impl capnp::message::Allocator for Builder {
             size + (ALLOC_SIZE - ((ALLOC_SIZE - 1) & size))
         };
 
-        let segment = Segment::create(&mut self.0.file, offset, size).unwrap();
-        let ptr = segment.as_ptr();
-        self.0.segments.push(segment);
-        (ptr, size / 8)
+        // a reused segment may be larger than was asked for
+        let mut c = self.container.lock().unwrap();
+        let segment = c.allocate(self.message, size).unwrap();
+        (segment.as_ptr(), segment.size as u32 / 8)
     }
 }
 
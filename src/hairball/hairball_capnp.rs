@@ -222,6 +222,18 @@ pub mod column {
     pub fn has_data(&self) -> bool {
       !self.reader.get_pointer_field(3).is_null()
     }
+    #[inline]
+    pub fn get_message(self) -> u32 {
+      self.reader.get_data_field::<u32>(0)
+    }
+    #[inline]
+    pub fn get_sorted(self) -> bool {
+      self.reader.get_bool_field(32)
+    }
+    #[inline]
+    pub fn get_schema(self) -> u64 {
+      self.reader.get_data_field::<u64>(1)
+    }
   }
 
   pub struct Builder<'a> { builder : ::capnp::private::layout::StructBuilder<'a> }
@@ -330,6 +342,30 @@ pub mod column {
     pub fn has_data(&self) -> bool {
       !self.builder.get_pointer_field(3).is_null()
     }
+    #[inline]
+    pub fn get_message(self) -> u32 {
+      self.builder.get_data_field::<u32>(0)
+    }
+    #[inline]
+    pub fn set_message(&mut self, value : u32)  {
+      self.builder.set_data_field::<u32>(0, value);
+    }
+    #[inline]
+    pub fn get_sorted(self) -> bool {
+      self.builder.get_bool_field(32)
+    }
+    #[inline]
+    pub fn set_sorted(&mut self, value : bool)  {
+      self.builder.set_bool_field(32, value);
+    }
+    #[inline]
+    pub fn get_schema(self) -> u64 {
+      self.builder.get_data_field::<u64>(1)
+    }
+    #[inline]
+    pub fn set_schema(&mut self, value : u64)  {
+      self.builder.set_data_field::<u64>(1, value);
+    }
   }
 
   pub struct Pipeline { _typeless : ::capnp::any_pointer::Pipeline }
@@ -348,7 +384,7 @@ pub mod column {
   }
   mod _private {
     use capnp::private::layout;
-    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 0, pointers : 4 };
+    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 2, pointers : 4 };
     pub const TYPE_ID: u64 = 0xd18cf772ef10499f;
   }
 }
@@ -408,6 +444,13 @@ pub mod local_entry {
     pub fn get_parent(self) -> u32 {
       self.reader.get_data_field::<u32>(0)
     }
+    #[inline]
+    pub fn get_guid(self) -> Result<data::Reader<'a>> {
+      self.reader.get_pointer_field(1).get_data(::std::ptr::null(), 0)
+    }
+    pub fn has_guid(&self) -> bool {
+      !self.reader.get_pointer_field(1).is_null()
+    }
   }
 
   pub struct Builder<'a> { builder : ::capnp::private::layout::StructBuilder<'a> }
@@ -481,6 +524,21 @@ pub mod local_entry {
     pub fn set_parent(&mut self, value : u32)  {
       self.builder.set_data_field::<u32>(0, value);
     }
+    #[inline]
+    pub fn get_guid(self) -> Result<data::Builder<'a>> {
+      self.builder.get_pointer_field(1).get_data(::std::ptr::null(), 0)
+    }
+    #[inline]
+    pub fn set_guid(&mut self, value : data::Reader)  {
+      self.builder.get_pointer_field(1).set_data(value);
+    }
+    #[inline]
+    pub fn init_guid(self, size : u32) -> data::Builder<'a> {
+      self.builder.get_pointer_field(1).init_data(size)
+    }
+    pub fn has_guid(&self) -> bool {
+      !self.builder.get_pointer_field(1).is_null()
+    }
   }
 
   pub struct Pipeline { _typeless : ::capnp::any_pointer::Pipeline }
@@ -493,7 +551,7 @@ pub mod local_entry {
   }
   mod _private {
     use capnp::private::layout;
-    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 1, pointers : 1 };
+    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 1, pointers : 2 };
     pub const TYPE_ID: u64 = 0xc3836dbbbb4aefaf;
   }
 }
@@ -547,6 +605,13 @@ pub mod external_entry {
       self.reader.get_data_field::<u32>(0)
     }
     #[inline]
+    pub fn get_guid(self) -> Result<data::Reader<'a>> {
+      self.reader.get_pointer_field(1).get_data(::std::ptr::null(), 0)
+    }
+    pub fn has_guid(&self) -> bool {
+      !self.reader.get_pointer_field(1).is_null()
+    }
+    #[inline]
     pub fn get_path(self) -> Result<text::Reader<'a>> {
       self.reader.get_pointer_field(0).get_text(::std::ptr::null(), 0)
     }
@@ -612,6 +677,21 @@ pub mod external_entry {
       self.builder.set_data_field::<u32>(0, value);
     }
     #[inline]
+    pub fn get_guid(self) -> Result<data::Builder<'a>> {
+      self.builder.get_pointer_field(1).get_data(::std::ptr::null(), 0)
+    }
+    #[inline]
+    pub fn set_guid(&mut self, value : data::Reader)  {
+      self.builder.get_pointer_field(1).set_data(value);
+    }
+    #[inline]
+    pub fn init_guid(self, size : u32) -> data::Builder<'a> {
+      self.builder.get_pointer_field(1).init_data(size)
+    }
+    pub fn has_guid(&self) -> bool {
+      !self.builder.get_pointer_field(1).is_null()
+    }
+    #[inline]
     pub fn get_path(self) -> Result<text::Builder<'a>> {
       self.builder.get_pointer_field(0).get_text(::std::ptr::null(), 0)
     }
@@ -638,7 +718,7 @@ pub mod external_entry {
   }
   mod _private {
     use capnp::private::layout;
-    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 1, pointers : 1 };
+    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 1, pointers : 2 };
     pub const TYPE_ID: u64 = 0xb75f998033fb1d84;
   }
 }
@@ -830,6 +910,141 @@ pub mod entity {
   pub type WhichBuilder<'a,> = Which<Result<::hairball_capnp::local_entry::Builder<'a>>,Result<::hairball_capnp::external_entry::Builder<'a>>>;
 }
 
+pub mod external_pin {
+  #![allow(unused_imports)]
+  use capnp::capability::{FromClientHook, FromTypelessPipeline};
+  use capnp::{text, data, Result};
+  use capnp::private::layout;
+  use capnp::traits::{FromStructBuilder, FromStructReader};
+  use capnp::{primitive_list, enum_list, struct_list, text_list, data_list, list_list};
+
+  pub struct Owned;
+  impl <'a> ::capnp::traits::Owned<'a> for Owned { type Reader = Reader<'a>; type Builder = Builder<'a>; }
+  impl <'a> ::capnp::traits::OwnedStruct<'a> for Owned { type Reader = Reader<'a>; type Builder = Builder<'a>; }
+  impl ::capnp::traits::Pipelined for Owned { type Pipeline = Pipeline; }
+
+  #[derive(Clone, Copy)]
+  pub struct Reader<'a> { reader : layout::StructReader<'a> }
+
+  impl <'a,> ::capnp::traits::HasTypeId for Reader<'a,>
+  {
+    #[inline]
+    fn type_id() -> u64 { _private::TYPE_ID }
+  }
+  impl <'a,> ::capnp::traits::FromStructReader<'a> for Reader<'a,>
+  {
+    fn new(reader: ::capnp::private::layout::StructReader<'a>) -> Reader<'a,> {
+      Reader { reader : reader,  }
+    }
+  }
+
+  impl <'a,> ::capnp::traits::FromPointerReader<'a> for Reader<'a,>
+  {
+    fn get_from_pointer(reader: &::capnp::private::layout::PointerReader<'a>) -> Result<Reader<'a,>> {
+      ::std::result::Result::Ok(::capnp::traits::FromStructReader::new(try!(reader.get_struct(::std::ptr::null()))))
+    }
+  }
+
+  impl <'a,> Reader<'a,>
+  {
+    pub fn borrow<'b>(&'b self) -> Reader<'b,> {
+      Reader { .. *self }
+    }
+
+    pub fn total_size(&self) -> Result<::capnp::MessageSize> {
+      self.reader.total_size()
+    }
+    #[inline]
+    pub fn get_hash(self) -> u64 {
+      self.reader.get_data_field::<u64>(0)
+    }
+    #[inline]
+    pub fn get_revision(self) -> u64 {
+      self.reader.get_data_field::<u64>(1)
+    }
+  }
+
+  pub struct Builder<'a> { builder : ::capnp::private::layout::StructBuilder<'a> }
+  impl <'a,> ::capnp::traits::HasStructSize for Builder<'a,>
+  {
+    #[inline]
+    fn struct_size() -> layout::StructSize { _private::STRUCT_SIZE }
+  }
+  impl <'a,> ::capnp::traits::HasTypeId for Builder<'a,>
+   {
+    #[inline]
+    fn type_id() -> u64 { _private::TYPE_ID }
+  }
+  impl <'a,> ::capnp::traits::FromStructBuilder<'a> for Builder<'a,>
+   {
+    fn new(builder : ::capnp::private::layout::StructBuilder<'a>) -> Builder<'a, > {
+      Builder { builder : builder,  }
+    }
+  }
+
+  impl <'a,> ::capnp::traits::FromPointerBuilder<'a> for Builder<'a,>
+   {
+    fn init_pointer(builder: ::capnp::private::layout::PointerBuilder<'a>, _size : u32) -> Builder<'a,> {
+      ::capnp::traits::FromStructBuilder::new(builder.init_struct(_private::STRUCT_SIZE))
+    }
+    fn get_from_pointer(builder: ::capnp::private::layout::PointerBuilder<'a>) -> Result<Builder<'a,>> {
+      ::std::result::Result::Ok(::capnp::traits::FromStructBuilder::new(try!(builder.get_struct(_private::STRUCT_SIZE, ::std::ptr::null()))))
+    }
+  }
+
+  impl <'a,> ::capnp::traits::SetPointerBuilder<Builder<'a,>> for Reader<'a,>
+   {
+    fn set_pointer_builder<'b>(pointer : ::capnp::private::layout::PointerBuilder<'b>, value : Reader<'a,>) -> Result<()> { pointer.set_struct(&value.reader) }
+  }
+
+  impl <'a,> Builder<'a,>
+   {
+    pub fn as_reader(self) -> Reader<'a,> {
+      ::capnp::traits::FromStructReader::new(self.builder.as_reader())
+    }
+    pub fn borrow<'b>(&'b mut self) -> Builder<'b,> {
+      Builder { .. *self }
+    }
+    pub fn borrow_as_reader<'b>(&'b self) -> Reader<'b,> {
+      ::capnp::traits::FromStructReader::new(self.builder.as_reader())
+    }
+
+    pub fn total_size(&self) -> Result<::capnp::MessageSize> {
+      self.builder.as_reader().total_size()
+    }
+    #[inline]
+    pub fn get_hash(self) -> u64 {
+      self.builder.get_data_field::<u64>(0)
+    }
+    #[inline]
+    pub fn set_hash(&mut self, value : u64)  {
+      self.builder.set_data_field::<u64>(0, value);
+    }
+    #[inline]
+    pub fn get_revision(self) -> u64 {
+      self.builder.get_data_field::<u64>(1)
+    }
+    #[inline]
+    pub fn set_revision(&mut self, value : u64)  {
+      self.builder.set_data_field::<u64>(1, value);
+    }
+  }
+
+  pub struct Pipeline { _typeless : ::capnp::any_pointer::Pipeline }
+  impl FromTypelessPipeline for Pipeline {
+    fn new(typeless : ::capnp::any_pointer::Pipeline) -> Pipeline {
+      Pipeline { _typeless : typeless,  }
+    }
+  }
+  impl Pipeline {
+  }
+  mod _private {
+    use capnp::private::layout;
+    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 2, pointers : 0 };
+    pub const TYPE_ID: u64 = 0xf1a2c8d35e7b9046;
+  }
+}
+
 pub mod hairball {
   #![allow(unused_imports)]
   use capnp::capability::{FromClientHook, FromTypelessPipeline};
@@ -902,6 +1117,31 @@ pub mod hairball {
     pub fn has_external(&self) -> bool {
       !self.reader.get_pointer_field(3).is_null()
     }
+    #[inline]
+    pub fn get_revision(self) -> u64 {
+      self.reader.get_data_field::<u64>(0)
+    }
+    #[inline]
+    pub fn get_pins(self) -> Result<struct_list::Reader<'a,::hairball_capnp::external_pin::Owned<>>> {
+      ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(4))
+    }
+    pub fn has_pins(&self) -> bool {
+      !self.reader.get_pointer_field(4).is_null()
+    }
+    #[inline]
+    pub fn get_schema_ids(self) -> Result<primitive_list::Reader<'a,u64>> {
+      ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(5))
+    }
+    pub fn has_schema_ids(&self) -> bool {
+      !self.reader.get_pointer_field(5).is_null()
+    }
+    #[inline]
+    pub fn get_schemas(self) -> Result<data_list::Reader<'a>> {
+      ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(6))
+    }
+    pub fn has_schemas(&self) -> bool {
+      !self.reader.get_pointer_field(6).is_null()
+    }
   }
 
   pub struct Builder<'a> { builder : ::capnp::private::layout::StructBuilder<'a> }
@@ -1012,6 +1252,59 @@ pub mod hairball {
     pub fn has_external(&self) -> bool {
       !self.builder.get_pointer_field(3).is_null()
     }
+    #[inline]
+    pub fn get_revision(self) -> u64 {
+      self.builder.get_data_field::<u64>(0)
+    }
+    #[inline]
+    pub fn set_revision(&mut self, value : u64)  {
+      self.builder.set_data_field::<u64>(0, value);
+    }
+    #[inline]
+    pub fn get_pins(self) -> Result<struct_list::Builder<'a,::hairball_capnp::external_pin::Owned<>>> {
+      ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(4))
+    }
+    #[inline]
+    pub fn set_pins(&mut self, value : struct_list::Reader<'a,::hairball_capnp::external_pin::Owned<>>) -> Result<()> {
+      ::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field(4), value)
+    }
+    #[inline]
+    pub fn init_pins(self, size : u32) -> struct_list::Builder<'a,::hairball_capnp::external_pin::Owned<>> {
+      ::capnp::traits::FromPointerBuilder::init_pointer(self.builder.get_pointer_field(4), size)
+    }
+    pub fn has_pins(&self) -> bool {
+      !self.builder.get_pointer_field(4).is_null()
+    }
+    #[inline]
+    pub fn get_schema_ids(self) -> Result<primitive_list::Builder<'a,u64>> {
+      ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(5))
+    }
+    #[inline]
+    pub fn set_schema_ids(&mut self, value : primitive_list::Reader<'a,u64>) -> Result<()> {
+      ::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field(5), value)
+    }
+    #[inline]
+    pub fn init_schema_ids(self, size : u32) -> primitive_list::Builder<'a,u64> {
+      ::capnp::traits::FromPointerBuilder::init_pointer(self.builder.get_pointer_field(5), size)
+    }
+    pub fn has_schema_ids(&self) -> bool {
+      !self.builder.get_pointer_field(5).is_null()
+    }
+    #[inline]
+    pub fn get_schemas(self) -> Result<data_list::Builder<'a>> {
+      ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(6))
+    }
+    #[inline]
+    pub fn set_schemas(&mut self, value : data_list::Reader<'a>) -> Result<()> {
+      ::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field(6), value)
+    }
+    #[inline]
+    pub fn init_schemas(self, size : u32) -> data_list::Builder<'a> {
+      ::capnp::traits::FromPointerBuilder::init_pointer(self.builder.get_pointer_field(6), size)
+    }
+    pub fn has_schemas(&self) -> bool {
+      !self.builder.get_pointer_field(6).is_null()
+    }
   }
 
   pub struct Pipeline { _typeless : ::capnp::any_pointer::Pipeline }
@@ -1030,7 +1323,7 @@ pub mod hairball {
   }
   mod _private {
     use capnp::private::layout;
-    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 0, pointers : 4 };
+    pub const STRUCT_SIZE : layout::StructSize = layout::StructSize { data : 1, pointers : 7 };
     pub const TYPE_ID: u64 = 0xdaf27d85b26ecdb9;
   }
 }
@@ -3,10 +3,17 @@ extern crate uuid;
 extern crate memmap;
 extern crate byteorder;
 extern crate semver;
+extern crate libc;
 
 use std::collections::HashMap;
-pub use container::{Error, file_uuid};
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+pub use container::{SegmentInfo, file_uuid};
+pub use column::{RowError, RowCause};
 
+#[macro_use]
+mod column;
 mod container;
 
 #[cfg(feature="build-schema")]
@@ -19,6 +26,47 @@ pub mod hairball_capnp;
 
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+#[derive(Debug)]
+pub enum Error {
+    // The header of the file is invalid
+    InvalidHeader,
+    Io(std::io::Error),
+    Capnp(capnp::Error),
+    // An external file has changed since it was pinned
+    PinMismatch(uuid::Uuid),
+    // An entity with the same path was already added
+    PathCollision(String),
+    // The path has no names in it
+    InvalidPath(String),
+    // The entities or columns of a hairball are inconsistent
+    Invalid(Vec<Invalid>),
+    // Column writers that were neither committed nor dropped
+    OpenColumnWriters(usize),
+}
+
+/// A problem found when validating the entities or columns of a hairball
+#[derive(Clone, Debug, PartialEq)]
+pub enum Invalid {
+    /// The parent of an entity is not an entity
+    ParentOutOfRange { entity: u32, parent: u32 },
+    /// The entity is it's own ancestor, this is reported once per cycle
+    ParentCycle(u32),
+    /// A row of a column references an entity that does not exist
+    DanglingReference { column: String, row: u32, entity: u32 },
+}
+
+impl std::convert::From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl std::convert::From<capnp::Error> for Error {
+    fn from(err: capnp::Error) -> Error {
+        Error::Capnp(err)
+    }
+}
+
 
 /// A `Builder` is used to construct a hairball
 pub struct Builder {
@@ -26,12 +74,40 @@ pub struct Builder {
     entity: Vec<Entity<String>>,
     external: Vec<uuid::Uuid>,
     external_lookup: HashMap<uuid::Uuid, u32>,
-    builder: capnp::message::Builder<container::Builder>
+    // the first local entity with a name under each parent
+    siblings: HashMap<(Option<u32>, String), u32>,
+    // the rows of each column that reference an entity that did not
+    // exist yet, see `validate`
+    references: HashMap<String, Vec<(u32, u32)>>,
+    pins: HashMap<uuid::Uuid, Pin>,
+    // the CodeGeneratorRequest of each embedded column schema
+    schemas: Vec<(u64, Vec<u8>)>,
+    revision: u64,
+    container: container::Writer,
+    builder: capnp::message::Builder<container::Builder>,
+    columns: Vec<ColumnBuilder>,
+    // the number of column writers that are still open, see `Pending`
+    writers: Arc<AtomicUsize>,
+    closed: bool
+}
+
+/// Each column is written into it's own message in the container
+struct ColumnBuilder {
+    name: String,
+    message: u32,
+    // columns that were copied from another hairball have no builder
+    builder: Option<capnp::message::Builder<container::Builder>>,
+    sorted: bool,
+    // the number of rows in the chunk if it is known
+    rows: u32,
+    schema: u64
 }
 
 impl Drop for Builder {
     fn drop(&mut self) {
-        self.write_entities();
+        // a builder that was not closed is still written, but there is
+        // no way to report an error
+        let _ = self.finish();
     }
 }
 
@@ -48,37 +124,119 @@ impl Builder {
     pub fn new_with_uuid<P>(p: P, uuid: uuid::Uuid) -> Result<Builder, Error>
         where P: AsRef<std::path::Path>
     {
-        let mut builder = capnp::message::Builder::new(
-            try!(container::Builder::new(p, uuid))
-        );
+        let container = try!(container::Writer::new(p, uuid));
+        let mut builder = capnp::message::Builder::new(container.message());
         builder.init_root::<hairball_capnp::hairball::Builder>();
 
         Ok(Builder {
             uuid: uuid,
             entity: Vec::new(),
+            container: container,
             builder: builder,
+            columns: Vec::new(),
+            writers: Arc::new(AtomicUsize::new(0)),
+            closed: false,
             external: Vec::new(),
-            external_lookup: HashMap::new()
+            external_lookup: HashMap::new(),
+            siblings: HashMap::new(),
+            references: HashMap::new(),
+            pins: HashMap::new(),
+            schemas: Vec::new(),
+            revision: 0
         })
     }
 
-    /// Adds a local entity to the file's keyspace
+    /// Adds a local entity to the file's keyspace. The name is not checked
+    /// against the names of it's siblings, so two entities can end up with
+    /// the same path, use `add_entity_at_path` to keep the paths unique.
     pub fn add_entity(&mut self, entry: LocalEntity<String>) -> u32 {
+        let idx = self.entity.len() as u32;
+        if let Some(ref name) = entry.name {
+            self.siblings.entry((entry.parent, name.clone())).or_insert(idx);
+        }
         self.entity.push(Entity::Local(entry));
-        self.entity.len() as u32 - 1
+        idx
+    }
+
+    /// Adds a local entity with the supplied path, such as `geometry/cube`.
+    /// The parents are created if there is no entity with their path,
+    /// otherwise the existing entity is used. An error is returned if
+    /// an entity already has the path.
+    pub fn add_entity_at_path(&mut self, path: &str) -> Result<u32, Error> {
+        let mut names: Vec<&str> = path.split('/').filter(|n| !n.is_empty()).collect();
+        let last = match names.pop() {
+            Some(last) => last,
+            None => return Err(Error::InvalidPath(path.to_owned()))
+        };
+
+        let mut parent = None;
+        for name in names {
+            let existing = self.siblings.get(&(parent, name.to_owned())).map(|&x| x);
+            parent = Some(match existing {
+                Some(idx) => idx,
+                None => self.add_entity(LocalEntity {
+                    name: Some(name.to_owned()),
+                    parent: parent,
+                    guid: None
+                })
+            });
+        }
+
+        if self.siblings.contains_key(&(parent, last.to_owned())) {
+            return Err(Error::PathCollision(path.to_owned()));
+        }
+        Ok(self.add_entity(LocalEntity {
+            name: Some(last.to_owned()),
+            parent: parent,
+            guid: None
+        }))
     }
 
     /// Adds a external entity to the file's key space
     pub fn add_external_entity(&mut self, entry: ExternalEntity<String>) -> u32 {
-        let insert = self.external_lookup.get(&entry.file).is_none();
+        self.add_external_file(entry.file);
+        self.entity.push(Entity::External(entry));
+        self.entity.len() as u32 - 1
+    }
+
+    /// Add a file to the external table if it is not already in it
+    fn add_external_file(&mut self, file: uuid::Uuid) {
+        let insert = self.external_lookup.get(&file).is_none();
         if insert {
-            self.external.push(entry.file.clone());
+            self.external.push(file);
             let id = self.external.len() as u32 - 1;
-            self.external_lookup.insert(entry.file.clone(), id);
+            self.external_lookup.insert(file, id);
         }
+    }
 
-        self.entity.push(Entity::External(entry));
-        self.entity.len() as u32 - 1
+    /// Pin an external file, readers of this hairball can then check
+    /// that `file` has not changed since it was referenced. The pin
+    /// records the revision and a hash of the contents of `file`.
+    pub fn pin(&mut self, file: &Reader) -> Result<(), Error> {
+        let pin = Pin {
+            hash: try!(file.content_hash()),
+            revision: file.revision()
+        };
+        self.add_pin(file.uuid(), pin);
+        Ok(())
+    }
+
+    /// Pin an external file with a pin that is already known, such as
+    /// one that was copied from another hairball
+    pub fn add_pin(&mut self, file: uuid::Uuid, pin: Pin) {
+        self.add_external_file(file);
+        self.pins.insert(file, pin);
+    }
+
+    /// Set the revision of this hairball, this should be incremented
+    /// each time a file with the same uuid is written
+    pub fn set_revision(&mut self, revision: u64) {
+        self.revision = revision;
+    }
+
+    /// Get the number of entities that have been added
+    pub fn entities_len(&self) -> usize {
+        self.entity.len()
     }
 
     /// internal function that writes the contents of the entities into a file
@@ -96,10 +254,109 @@ impl Builder {
                 files.set(i as u32, file.as_bytes());
             }
         }
+        if self.pins.len() > 0 {
+            let mut pins = root.borrow().init_pins(self.external.len() as u32);
+            for (i, file) in self.external.iter().enumerate() {
+                if let Some(pin) = self.pins.get(file) {
+                    let mut p = pins.borrow().get(i as u32);
+                    p.set_hash(pin.hash);
+                    p.set_revision(pin.revision);
+                }
+            }
+        }
+        root.set_revision(self.revision);
+    }
+
+    /// Record that `row` of `column` references `entity`. A reference to
+    /// an entity that does not exist yet is kept so `validate` can check
+    /// that the entity was added before the hairball is closed.
+    pub fn reference(&mut self, column: &str, row: u32, entity: u32) {
+        if (entity as usize) < self.entity.len() {
+            return;
+        }
+        self.references.entry(column.to_owned())
+            .or_insert(Vec::new())
+            .push((row, entity));
+    }
+
+    /// Check that every parent is an entity, that there are no cycles in
+    /// the parents and that the columns only reference entities that exist
+    pub fn validate(&self) -> Vec<Invalid> {
+        let parents: Vec<Option<u32>> = self.entity.iter().map(|e| {
+            match *e {
+                Entity::Local(ref e) => e.parent,
+                Entity::External(_) => None
+            }
+        }).collect();
+        let mut invalid = validate_parents(&parents[..]);
+
+        let mut columns: Vec<_> = self.references.iter().collect();
+        columns.sort();
+        for (column, rows) in columns {
+            for &(row, entity) in rows {
+                if entity as usize >= self.entity.len() {
+                    invalid.push(Invalid::DanglingReference {
+                        column: column.clone(),
+                        row: row,
+                        entity: entity
+                    });
+                }
+            }
+        }
+        invalid
+    }
+
+    /// internal function that writes the entities, the column table and
+    /// the header of the container, this only happens once
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.write_entities();
+        self.write_columns();
+        self.write_schemas();
+        self.container.close()
+    }
+
+    /// internal function that gives up on the hairball, the header is
+    /// not written and the file is removed
+    fn abandon(&mut self) {
+        self.closed = true;
+        self.container.abandon();
+    }
+
+    /// internal function that checks that every column writer was
+    /// committed or dropped, the hairball is abandoned if one was not
+    fn check_writers(&mut self) -> Result<(), Error> {
+        let writers = self.writers.load(Ordering::SeqCst);
+        if writers > 0 {
+            self.abandon();
+            return Err(Error::OpenColumnWriters(writers));
+        }
+        Ok(())
+    }
+
+    /// Write the `metadata` to finalize the hairball. If the hairball
+    /// fails `validate` the problems are returned as `Error::Invalid`,
+    /// the header is not written and the file is removed. The same
+    /// happens if a `ColumnWriter` or `ChunkedWriter` is still open.
+    pub fn close(mut self) -> Result<(), Error> {
+        try!(self.check_writers());
+        let invalid = self.validate();
+        if invalid.len() > 0 {
+            self.abandon();
+            return Err(Error::Invalid(invalid));
+        }
+        self.finish()
     }
 
-    /// Write the `metadata` to finalize the hairball
-    pub fn close(self) {}
+    /// Write the `metadata` to finalize the hairball without checking
+    /// it with `validate`, every column writer must still be closed
+    pub fn close_unchecked(mut self) -> Result<(), Error> {
+        try!(self.check_writers());
+        self.finish()
+    }
 
     /// Get the current file uuid
     pub fn uuid(&self) -> uuid::Uuid {
@@ -111,38 +368,275 @@ impl Builder {
         self.uuid = uuid;
     }
 
+    /// internal function that writes the column table into the root
+    fn write_columns(&mut self) {
+        if self.columns.len() == 0 {
+            return;
+        }
+
+        let root = self.builder.get_root::<hairball_capnp::hairball::Builder>().unwrap();
+        let mut column = root.init_columns();
+        for (i, c) in self.columns.iter().enumerate() {
+            column.set_name(&c.name[..]);
+            column.set_message(c.message);
+            column.set_sorted(c.sorted);
+            column.set_schema(c.schema);
+            if i + 1 != self.columns.len() {
+                column = column.init_next();
+            }
+        }
+    }
+
+    /// internal function that writes the schemas used by the columns
+    fn write_schemas(&mut self) {
+        let columns = &self.columns;
+        let schemas: Vec<&(u64, Vec<u8>)> = self.schemas.iter()
+            .filter(|&&(id, _)| columns.iter().any(|c| c.schema == id))
+            .collect();
+        if schemas.len() == 0 {
+            return;
+        }
+
+        let mut root = self.builder.get_root::<hairball_capnp::hairball::Builder>().unwrap();
+        {
+            let mut ids = root.borrow().init_schema_ids(schemas.len() as u32);
+            for (i, &&(id, _)) in schemas.iter().enumerate() {
+                ids.set(i as u32, id);
+            }
+        }
+        let mut requests = root.init_schemas(schemas.len() as u32);
+        for (i, &&(_, ref request)) in schemas.iter().enumerate() {
+            requests.set(i as u32, &request[..]);
+        }
+    }
+
+    /// Record the capnp type id of the struct the column is stored as,
+    /// `request` is a serialized CodeGeneratorRequest that describes it
+    /// so tools can read the column without knowing it's type. Only the
+    /// last chunk of the column is marked.
+    pub fn set_schema(&mut self, column: &str, id: u64, request: Option<&[u8]>) {
+        if let Some(c) = self.columns.iter_mut().rev().find(|c| c.name == column) {
+            c.schema = id;
+        }
+        if let Some(request) = request {
+            self.add_schema(id, request);
+        }
+    }
+
+    /// Embed the schema of a type, this does nothing if it was already added
+    fn add_schema(&mut self, id: u64, request: &[u8]) {
+        if !self.schemas.iter().any(|&(i, _)| i == id) {
+            self.schemas.push((id, request.to_owned()));
+        }
+    }
+
+    /// Create a column that can be filled independently of this `Builder`,
+    /// the `ColumnWriter` may be sent to another thread. The column is not
+    /// part of the hairball until it is passed to `commit`, if it is
+    /// dropped instead it's segments are reused by the next column.
+    pub fn column_writer(&self, name: &str) -> ColumnWriter {
+        ColumnWriter::new(name, &self.container, &self.writers, self.entity.len())
+    }
+
+    /// Add a column that was filled by a `ColumnWriter` to the hairball,
+    /// any existing column with the same name is replaced.
+    pub fn commit(&mut self, column: ColumnWriter) {
+        self.remove_column(&column.name);
+        self.append(column);
+    }
+
+    /// Add a column that was filled by a `ColumnWriter` as another chunk
+    /// of the column with the same name, readers see the rows of every
+    /// chunk in the order they were appended.
+    pub fn append(&mut self, mut column: ColumnWriter) {
+        let offset = self.rows(&column.name);
+        let references = std::mem::replace(&mut column.references, Vec::new());
+        for (row, entity) in references {
+            self.reference(&column.name, offset + row, entity);
+        }
+        let schema = match column.schema {
+            Some((id, ref request)) => {
+                if let Some(ref request) = *request {
+                    self.add_schema(id, &request[..]);
+                }
+                id
+            }
+            None => 0
+        };
+        self.columns.push(ColumnBuilder {
+            name: column.name.clone(),
+            message: column.message,
+            builder: column.builder.take(),
+            sorted: column.sorted,
+            rows: column.rows,
+            schema: schema
+        });
+    }
+
+    /// The number of rows in every chunk of the column, only rows that
+    /// were added with `append_rows` or a `ColumnWriter` are counted
+    fn rows(&self, name: &str) -> u32 {
+        self.columns.iter()
+            .filter(|c| c.name == name)
+            .fold(0, |sum, c| sum + c.rows)
+    }
+
+    /// Remove every chunk of a column, returns false if the column did
+    /// not exist. The segments of the removed chunks are reused by the
+    /// columns that are written after this, any that are not reused are
+    /// left empty in the file until it is rewritten with
+    /// `hairball_tools::repack`.
+    pub fn remove_column(&mut self, name: &str) -> bool {
+        self.references.remove(name);
+        let mut found = false;
+        while let Some(idx) = self.columns.iter().position(|c| c.name == name) {
+            // the message builder points into the segments, so it is
+            // dropped before they are freed
+            let message = self.columns.remove(idx).message;
+            self.container.free_message(message);
+            found = true;
+        }
+        found
+    }
+
+    /// Create a new chunk at the end of the column
+    fn add_chunk(&mut self, name: &str, rows: u32) -> usize {
+        let message = self.container.message();
+        self.columns.push(ColumnBuilder {
+            name: name.to_owned(),
+            message: message.id(),
+            builder: Some(capnp::message::Builder::new(message)),
+            sorted: false,
+            rows: rows,
+            schema: 0
+        });
+        self.columns.len() - 1
+    }
+
+    /// Create a writer that writes the rows of a column in chunks of
+    /// `chunk_size` rows, each chunk is written with `write`. The rows
+    /// are part of the hairball once the writer is passed to
+    /// `commit_chunks`.
+    pub fn chunked_writer<T>(&self, name: &str, chunk_size: usize,
+                             write: fn(&mut ColumnWriter, &[T])) -> ChunkedWriter<T> {
+        ChunkedWriter {
+            container: self.container.clone(),
+            pending: Pending::new(&self.writers),
+            entities: self.entity.len(),
+            name: name.to_owned(),
+            rows: Vec::new(),
+            chunk_size: std::cmp::max(chunk_size, 1),
+            write: write,
+            chunks: Vec::new(),
+            len: 0
+        }
+    }
+
+    /// Add the chunks of a `ChunkedWriter` to the hairball, any existing
+    /// column with the same name is replaced.
+    pub fn commit_chunks<T>(&mut self, mut writer: ChunkedWriter<T>) {
+        writer.flush();
+        self.remove_column(&writer.name);
+        for chunk in writer.chunks.drain(..) {
+            self.append(chunk);
+        }
+    }
+
     /// access the column that matches this name, iff it does not
-    /// exist a column will be created with the provided name
+    /// exist a column will be created with the provided name. If the
+    /// column has several chunks this is the last one.
     pub fn column(&mut self, name: &str) -> Result<capnp::any_pointer::Builder, capnp::Error> {
-        let root = self.builder.get_root::<hairball_capnp::hairball::Builder>().unwrap();
-        let mut column = if root.has_columns() {
-            try!(root.get_columns())
-        } else {
-            root.init_columns()
+        let idx = match self.columns.iter().rposition(|c| c.name == name) {
+            Some(idx) => idx,
+            None => self.add_chunk(name, 0)
         };
+        self.chunk_root(idx)
+    }
 
-        loop {
-            // found a column that is named ans matches outs
-            if column.has_name() && &try!(column.borrow().get_name())[..] == name {
-                break;
-            // found uninitialized column
-            } else if !column.has_name() {
-                column.borrow().set_name(name);
-                break;
+    /// Add a chunk of `rows` rows to the end of the column, unlike
+    /// `column` this keeps the rows that were already written so a
+    /// column can be written by several calls.
+    pub fn append_rows(&mut self, name: &str, rows: u32) -> Result<capnp::any_pointer::Builder, capnp::Error> {
+        let idx = self.add_chunk(name, rows);
+        self.chunk_root(idx)
+    }
+
+    fn chunk_root(&mut self, idx: usize) -> Result<capnp::any_pointer::Builder, capnp::Error> {
+        match self.columns[idx].builder {
+            Some(ref mut builder) => builder.get_root(),
+            None => Err(capnp::Error::new_decode_error(
+                "column was copied from another hairball and cannot be modified", None
+            ))
+        }
+    }
+
+    /// Copy a column from another hairball. If the column is stored in it's
+    /// own message it's segments are copied byte for byte, otherwise the
+    /// column is copied using capnp. Every chunk of the column is copied.
+    /// Any existing column with the same name is replaced. Returns false
+    /// if `src` did not have the column.
+    pub fn copy_column(&mut self, src: &Reader, name: &str) -> Result<bool, Error> {
+        self.remove_column(name);
+
+        let entries = src.column_entries(name);
+        for c in &entries {
+            match c.get_message() {
+                0 => {
+                    if !c.has_data() {
+                        continue;
+                    }
+                    let idx = self.add_chunk(name, 0);
+                    self.columns[idx].sorted = c.get_sorted();
+                    self.columns[idx].schema = c.get_schema();
+                    let mut column = try!(self.chunk_root(idx));
+                    try!(column.set_as(c.get_data()));
+                }
+                message => {
+                    let message = try!(self.container.copy_message(&src.container, message));
+                    self.columns.push(ColumnBuilder {
+                        name: name.to_owned(),
+                        message: message,
+                        builder: None,
+                        sorted: c.get_sorted(),
+                        rows: 0,
+                        schema: c.get_schema()
+                    });
+                }
             }
+            if let Some(request) = src.schema(c.get_schema()) {
+                self.add_schema(c.get_schema(), request);
+            }
+        }
+        Ok(entries.len() > 0)
+    }
 
-            column = if column.has_next() {
-                try!(column.get_next())
-            } else {
-                column.init_next()
-            };
+    /// Mark a column as having it's rows sorted by the entity they belong
+    /// to, readers can then find the rows of an entity without reading
+    /// the whole column. If the column has several chunks only the last
+    /// one is marked. This does nothing if the column does not exist.
+    pub fn set_sorted(&mut self, column: &str, sorted: bool) {
+        if let Some(c) = self.columns.iter_mut().rev().find(|c| c.name == column) {
+            c.sorted = sorted;
         }
+    }
 
-        Ok(if column.has_data() {
-            column.get_data()
-        } else {
-            column.init_data()
-        })
+    /// Delete the rows of `column` that belong to `entities` in the
+    /// earlier layers of an `Overlay`, this replaces any rows that were
+    /// already deleted from the column.
+    pub fn delete_rows(&mut self, column: &str, entities: &[u32]) -> Result<(), capnp::Error> {
+        let name = tombstone_column(column);
+        {
+            let column = try!(self.column(&name));
+            let mut list = column.initn_as::<capnp::primitive_list::Builder<u32>>(entities.len() as u32);
+            for (i, &e) in entities.iter().enumerate() {
+                list.set(i as u32, e);
+            }
+        }
+        self.references.remove(&name);
+        for (i, &e) in entities.iter().enumerate() {
+            self.reference(&name, i as u32, e);
+        }
+        Ok(())
     }
 
     pub fn mapping<E>(&mut self) -> BuilderMapping<E>
@@ -155,6 +649,217 @@ impl Builder {
     }
 }
 
+/// A column that is written into it's own message of a hairball's
+/// container, see `Builder::column_writer`
+pub struct ColumnWriter {
+    name: String,
+    message: u32,
+    // this is only None once the writer was committed
+    builder: Option<capnp::message::Builder<container::Builder>>,
+    container: container::Writer,
+    // the number of entities when the writer was created
+    entities: usize,
+    // the rows that reference an entity that did not exist yet
+    references: Vec<(u32, u32)>,
+    sorted: bool,
+    pending: Pending,
+    rows: u32,
+    // the type id and CodeGeneratorRequest, see `Builder::set_schema`
+    schema: Option<(u64, Option<Vec<u8>>)>
+}
+
+// SAFETY: `capnp::message::Builder` is not `Send` because it's arena
+// keeps raw pointers to the segments it was given. Those segments are
+// allocated from the container for this writer's message only, so no
+// other builder holds a pointer to them, and they stay mapped at the same
+// address until the container is dropped, which the `Arc` in the
+// allocator prevents. The segments are only freed once the builder is
+// dropped, either by the writer's `drop` or after the column was replaced
+// in the `Builder`. Allocating a new segment locks the container's mutex,
+// so writers on different threads never touch the container at the same
+// time. Nothing in the writer is shared with another thread, it is not
+// `Sync`.
+unsafe impl Send for ColumnWriter {}
+
+impl Drop for ColumnWriter {
+    fn drop(&mut self) {
+        // a writer that was not committed is not part of the hairball,
+        // the builder points into the segments so it is dropped first
+        if self.builder.take().is_some() {
+            self.container.free_message(self.message);
+        }
+    }
+}
+
+impl ColumnWriter {
+    /// internal function that creates a writer for a new message of
+    /// `container`, `entities` is the number of entities that exist
+    fn new(name: &str, container: &container::Writer, writers: &Arc<AtomicUsize>,
+           entities: usize) -> ColumnWriter {
+        let message = container.message();
+        ColumnWriter {
+            name: name.to_owned(),
+            message: message.id(),
+            builder: Some(capnp::message::Builder::new(message)),
+            container: container.clone(),
+            entities: entities,
+            references: Vec::new(),
+            sorted: false,
+            pending: Pending::new(writers),
+            rows: 0,
+            schema: None
+        }
+    }
+
+    /// The name of the column
+    pub fn name(&self) -> &str {
+        &self.name[..]
+    }
+}
+
+/// Writes the rows of a column in chunks so every row does not need to be
+/// held in memory, see `Builder::chunked_writer`
+pub struct ChunkedWriter<T> {
+    container: container::Writer,
+    pending: Pending,
+    // the number of entities when the writer was created
+    entities: usize,
+    name: String,
+    rows: Vec<T>,
+    chunk_size: usize,
+    write: fn(&mut ColumnWriter, &[T]),
+    chunks: Vec<ColumnWriter>,
+    len: usize
+}
+
+impl<T> ChunkedWriter<T> {
+    /// Add a row to the column, once there are enough rows they are
+    /// written as a chunk
+    pub fn push(&mut self, row: T) {
+        self.rows.push(row);
+        self.len += 1;
+        if self.rows.len() >= self.chunk_size {
+            self.flush();
+        }
+    }
+
+    /// The number of rows that have been pushed
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Write the buffered rows as a chunk
+    fn flush(&mut self) {
+        if self.rows.len() == 0 {
+            return;
+        }
+
+        let mut chunk = ColumnWriter::new(&self.name, &self.container,
+                                          &self.pending.0, self.entities);
+        (self.write)(&mut chunk, &self.rows[..]);
+        self.rows.clear();
+        self.chunks.push(chunk);
+    }
+}
+
+/// Counts a column writer of a `Builder` until the writer is dropped,
+/// `Builder::close` fails while any are still open
+struct Pending(Arc<AtomicUsize>);
+
+impl Pending {
+    fn new(writers: &Arc<AtomicUsize>) -> Pending {
+        writers.fetch_add(1, Ordering::SeqCst);
+        Pending(writers.clone())
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Something that a column can be written into, this is implemented by
+/// both `Builder` and `ColumnWriter`
+pub trait Columns {
+    /// access the column that matches this name
+    fn column(&mut self, name: &str) -> Result<capnp::any_pointer::Builder, capnp::Error>;
+
+    /// Add `rows` rows to the end of the column, rows that were already
+    /// written are kept. A `ColumnWriter` holds a single chunk so this
+    /// replaces it's rows.
+    fn append_rows(&mut self, name: &str, rows: u32) -> Result<capnp::any_pointer::Builder, capnp::Error>;
+
+    /// Record that `row` of the column references `entity`, this is
+    /// checked when the `Builder` is closed. The row is counted from the
+    /// start of the last chunk that was appended.
+    fn reference(&mut self, name: &str, row: u32, entity: u32);
+
+    /// Mark the column as having it's rows sorted by entity
+    fn set_sorted(&mut self, name: &str, sorted: bool);
+
+    /// Record the schema the column is stored as, see `Builder::set_schema`
+    fn set_schema(&mut self, name: &str, id: u64, request: Option<&[u8]>);
+}
+
+impl Columns for Builder {
+    fn column(&mut self, name: &str) -> Result<capnp::any_pointer::Builder, capnp::Error> {
+        Builder::column(self, name)
+    }
+
+    fn append_rows(&mut self, name: &str, rows: u32) -> Result<capnp::any_pointer::Builder, capnp::Error> {
+        Builder::append_rows(self, name, rows)
+    }
+
+    fn reference(&mut self, name: &str, row: u32, entity: u32) {
+        let last = self.columns.iter().rev()
+            .find(|c| c.name == name)
+            .map(|c| c.rows)
+            .unwrap_or(0);
+        let offset = self.rows(name) - last;
+        Builder::reference(self, name, offset + row, entity)
+    }
+
+    fn set_sorted(&mut self, name: &str, sorted: bool) {
+        Builder::set_sorted(self, name, sorted)
+    }
+
+    fn set_schema(&mut self, name: &str, id: u64, request: Option<&[u8]>) {
+        Builder::set_schema(self, name, id, request)
+    }
+}
+
+impl Columns for ColumnWriter {
+    fn column(&mut self, name: &str) -> Result<capnp::any_pointer::Builder, capnp::Error> {
+        if name != self.name {
+            return Err(capnp::Error::new_decode_error(
+                "column writer was created for a different column", Some(name.to_owned())
+            ));
+        }
+        self.builder.as_mut().expect("column writer was committed").get_root()
+    }
+
+    fn append_rows(&mut self, name: &str, rows: u32) -> Result<capnp::any_pointer::Builder, capnp::Error> {
+        self.rows = rows;
+        self.references.clear();
+        self.column(name)
+    }
+
+    fn reference(&mut self, _: &str, row: u32, entity: u32) {
+        if entity as usize >= self.entities {
+            self.references.push((row, entity));
+        }
+    }
+
+    fn set_sorted(&mut self, _: &str, sorted: bool) {
+        self.sorted = sorted;
+    }
+
+    fn set_schema(&mut self, _: &str, id: u64, request: Option<&[u8]>) {
+        self.schema = Some((id, request.map(|r| r.to_owned())));
+    }
+}
+
 pub struct BuilderMapping<'a, E> {
     writer: &'a mut Builder,
     entities: HashMap<E, u32>
@@ -178,6 +883,101 @@ impl<'a, E> BuilderMapping<'a, E> {
             idx
         }
     }
+
+    /// Add a local entity whose parent is the engine entity `parent`, any
+    /// parent set on `local` is replaced. Returns None if `parent` has not
+    /// been added to the mapping, see `reference` for parents that belong
+    /// to another hairball.
+    pub fn add_child(&mut self, e: E, local: LocalEntity<String>, parent: &E) -> Option<u32>
+        where E: Eq + std::hash::Hash
+    {
+        match self.entity(parent) {
+            Some(p) => Some(self.add_entity(e, local.parent(p))),
+            None => None
+        }
+    }
+
+    /// Add an entity that lives in another hairball
+    pub fn add_external_entity(&mut self, e: E, external: ExternalEntity<String>) -> u32
+        where E: Eq + std::hash::Hash
+    {
+        if let Some(&idx) = self.entities.get(&e) {
+            idx
+        } else {
+            let idx = self.writer.add_external_entity(external);
+            self.entities.insert(e, idx);
+            idx
+        }
+    }
+
+    /// Get the id of `e`, if it has not been added to the mapping and it was
+    /// loaded from another hairball an external entity is added for it.
+    /// Returns None if `e` is not mapped and `origin` does not know of it.
+    pub fn reference<O>(&mut self, e: &E, origin: &O) -> Option<u32>
+        where E: Eq + std::hash::Hash + Clone,
+              O: Origin<E> + ?Sized
+    {
+        if let Some(idx) = self.entity(e) {
+            return Some(idx);
+        }
+        origin.origin(e).map(|external| self.add_external_entity(e.clone(), external))
+    }
+}
+
+/// Describes where the engine entities that were loaded from a
+/// hairball came from, see `BuilderMapping::reference`
+pub trait Origin<E> {
+    /// The external entity that refers to `e` or None if `e` was
+    /// not loaded from a hairball
+    fn origin(&self, e: &E) -> Option<ExternalEntity<String>>;
+}
+
+impl<'a, E> Origin<E> for ReaderMapping<'a, E>
+    where E: Eq + std::hash::Hash + Clone
+{
+    fn origin(&self, e: &E) -> Option<ExternalEntity<String>> {
+        let idx = {
+            let mut origins = self.origins.borrow_mut();
+            if origins.is_none() {
+                let mut index = HashMap::new();
+                for (i, x) in self.entities.iter().enumerate() {
+                    if let Some(ref x) = *x {
+                        index.entry(x.clone()).or_insert(i);
+                    }
+                }
+                *origins = Some(index);
+            }
+            match origins.as_ref().and_then(|o| o.get(e)) {
+                Some(&idx) => idx,
+                None => return None
+            }
+        };
+
+        match self.reader.get_entity(idx) {
+            // refer to the hairball the entity actually lives in
+            Some(Entity::External(external)) => Some(external.into_owned()),
+            Some(local) => {
+                let guid = local.guid().map(|&g| g);
+                match (self.reader.path(idx), guid) {
+                    (None, None) => None,
+                    (name, guid) => Some(ExternalEntity {
+                        file: self.reader.uuid(),
+                        name: name,
+                        guid: guid
+                    })
+                }
+            }
+            None => None
+        }
+    }
+}
+
+impl<'a, E> Origin<E> for [ReaderMapping<'a, E>]
+    where E: Eq + std::hash::Hash + Clone
+{
+    fn origin(&self, e: &E) -> Option<ExternalEntity<String>> {
+        self.iter().filter_map(|m| m.origin(e)).next()
+    }
 }
 
 impl<'a, E> std::ops::Deref for BuilderMapping<'a, E> {
@@ -192,7 +992,8 @@ impl<'a, E> std::ops::DerefMut for BuilderMapping<'a, E> {
 #[derive(Clone, Copy, Debug)]
 pub struct LocalEntity<T> {
     pub name: Option<T>,
-    pub parent: Option<u32>
+    pub parent: Option<u32>,
+    pub guid: Option<uuid::Uuid>
 }
 
 impl<T> LocalEntity<T> {
@@ -200,7 +1001,8 @@ impl<T> LocalEntity<T> {
     pub fn named(name: T) -> LocalEntity<T> {
         LocalEntity {
             name: Some(name),
-            parent: None
+            parent: None,
+            guid: None
         }
     }
 
@@ -208,7 +1010,8 @@ impl<T> LocalEntity<T> {
     pub fn anonymous() -> LocalEntity<T> {
         LocalEntity {
             name: None,
-            parent: None
+            parent: None,
+            guid: None
         }
     }
 
@@ -216,16 +1019,33 @@ impl<T> LocalEntity<T> {
     pub fn parent(self, parent: u32) -> LocalEntity<T> {
         LocalEntity {
             parent: Some(parent),
-            name: self.name,
+            .. self
+        }
+    }
+
+    /// Set the guid of this object, an entity that is not given a
+    /// guid does not have one
+    pub fn guid(self, guid: uuid::Uuid) -> LocalEntity<T> {
+        LocalEntity {
+            guid: Some(guid),
+            .. self
         }
     }
 }
 
+/// Read a guid, anything that is not 16 bytes is ignored
+fn read_guid(data: capnp::Result<&[u8]>) -> Option<uuid::Uuid> {
+    data.ok().and_then(|x| uuid::Uuid::from_bytes(x))
+}
+
 impl LocalEntity<String> {
     fn write(&self, mut builder: hairball_capnp::local_entry::Builder) {
         if let Some(ref name) = self.name {
             builder.set_name(&name[..])
         }
+        if let Some(ref guid) = self.guid {
+            builder.set_guid(guid.as_bytes())
+        }
         builder.set_parent(if let Some(id) = self.parent { id } else { !0 });
     }
 }
@@ -241,9 +1061,23 @@ impl<'a> LocalEntity<&'a str> {
             parent: match reader.get_parent() {
                 0xffffffff => None,
                 x => Some(x)
+            },
+            guid: if reader.has_guid() {
+                read_guid(reader.get_guid())
+            } else {
+                None
             }
         })
     }
+
+    /// Copy the entity so that it can be added to a `Builder`
+    pub fn into_owned(self) -> LocalEntity<String> {
+        LocalEntity {
+            name: self.name.map(|n| n.to_owned()),
+            parent: self.parent,
+            guid: self.guid
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -253,10 +1087,11 @@ pub enum Entity<T> {
 }
 
 impl<'a> Entity<&'a str> {
+    /// The name of a local entity or the path of an external entity
     pub fn name(&self) -> Option<&str> {
         match *self {
             Entity::Local(ref e) => e.name,
-            Entity::External(ref e) => Some(&e.name)
+            Entity::External(ref e) => e.name
         }
     }
 
@@ -273,6 +1108,15 @@ impl<'a> Entity<&'a str> {
             Entity::External(ref e) => Some(&e.file)
         }
     }
+
+    /// The guid of a local entity or the guid that an external
+    /// entity references
+    pub fn guid(&self) -> Option<&uuid::Uuid> {
+        match *self {
+            Entity::Local(ref e) => e.guid.as_ref(),
+            Entity::External(ref e) => e.guid.as_ref()
+        }
+    }
 }
 
 impl Entity<String> {
@@ -303,17 +1147,39 @@ impl<'a> Entity<&'a str> {
     }
 }
 
+/// A reference to an entity in another hairball, the entity is
+/// found using either it's path or it's guid
 #[derive(Debug)]
 pub struct ExternalEntity<T> {
     file: uuid::Uuid,
-    name: T
+    name: Option<T>,
+    guid: Option<uuid::Uuid>
 }
 
 impl ExternalEntity<String> {
+    /// Reference an entity by it's path
     pub fn new(file: uuid::Uuid, name: String) -> ExternalEntity<String> {
         ExternalEntity {
             file: file,
-            name: name
+            name: Some(name),
+            guid: None
+        }
+    }
+
+    /// Reference an entity by it's guid
+    pub fn by_guid(file: uuid::Uuid, guid: uuid::Uuid) -> ExternalEntity<String> {
+        ExternalEntity {
+            file: file,
+            name: None,
+            guid: Some(guid)
+        }
+    }
+
+    /// Set the guid of the entity as well as it's path
+    pub fn guid(self, guid: uuid::Uuid) -> ExternalEntity<String> {
+        ExternalEntity {
+            guid: Some(guid),
+            .. self
         }
     }
 
@@ -322,7 +1188,12 @@ impl ExternalEntity<String> {
     {
         let file = lookup.get(&self.file).expect("Expected uuid to be in table");
         builder.set_file(*file);
-        builder.set_path(&self.name[..]);
+        if let Some(ref name) = self.name {
+            builder.set_path(&name[..]);
+        }
+        if let Some(ref guid) = self.guid {
+            builder.set_guid(guid.as_bytes());
+        }
     }
 }
 
@@ -333,29 +1204,256 @@ impl<'a> ExternalEntity<&'a str> {
         let uuid = root.external(idx as usize).unwrap();
         Ok(ExternalEntity {
             file: uuid,
-            name: try!(reader.get_path())
+            name: if reader.has_path() {
+                Some(try!(reader.get_path()))
+            } else {
+                None
+            },
+            guid: if reader.has_guid() {
+                read_guid(reader.get_guid())
+            } else {
+                None
+            }
         })
     }
+
+    /// Copy the entity so that it can be added to a `Builder`
+    pub fn into_owned(self) -> ExternalEntity<String> {
+        ExternalEntity {
+            file: self.file,
+            name: self.name.map(|n| n.to_owned()),
+            guid: self.guid
+        }
+    }
+}
+
+/// A set of hairballs that external entities can be resolved against
+pub struct Library {
+    readers: Vec<Reader>,
+    files: HashMap<uuid::Uuid, usize>
+}
+
+/// An entity in one of the hairballs of a `Library`
+#[derive(Clone, Copy)]
+pub struct Link<'a> {
+    pub reader: &'a Reader,
+    pub entity: usize
+}
+
+impl Library {
+    /// Create an empty library
+    pub fn new() -> Library {
+        Library {
+            readers: Vec::new(),
+            files: HashMap::new()
+        }
+    }
+
+    /// Add a hairball to the library, if a hairball with the
+    /// same uuid is already in the library it is replaced
+    pub fn add(&mut self, reader: Reader) {
+        if let Some(&idx) = self.files.get(&reader.uuid()) {
+            self.readers[idx] = reader;
+            return;
+        }
+
+        self.files.insert(reader.uuid(), self.readers.len());
+        self.readers.push(reader);
+    }
+
+    /// Get a hairball by it's uuid
+    pub fn get(&self, file: &uuid::Uuid) -> Option<&Reader> {
+        self.files.get(file).map(|&idx| &self.readers[idx])
+    }
+
+    /// Get the hairballs in the library
+    pub fn readers(&self) -> &[Reader] {
+        &self.readers[..]
+    }
+
+    /// Find the local entity that `entity` refers to. A local entity
+    /// refers to itself, an external entity is followed into the hairball
+    /// it references. Returns None if the hairball is not in the library
+    /// or it does not have the entity.
+    pub fn resolve<'a>(&'a self, reader: &'a Reader, entity: u32) -> Option<Link<'a>> {
+        let mut link = Link {
+            reader: reader,
+            entity: entity as usize
+        };
+
+        // each hop moves to a hairball in the library, so
+        // anything longer then this must be a cycle
+        for _ in 0..self.readers.len() + 1 {
+            let r = link.reader;
+            link = match r.get_entity(link.entity) {
+                Some(Entity::Local(_)) => return Some(link),
+                Some(e @ Entity::External(_)) => {
+                    let idx = match e.file().and_then(|f| self.files.get(f)) {
+                        Some(&idx) => idx,
+                        None => return None
+                    };
+                    let target = &self.readers[idx];
+                    let entity = match (e.name(), e.guid()) {
+                        (Some(name), _) => target.lookup(name),
+                        (None, Some(guid)) => target.find_guid(guid),
+                        (None, None) => None
+                    };
+                    match entity {
+                        Some(entity) => Link {
+                            reader: target,
+                            entity: entity
+                        },
+                        None => return None
+                    }
+                }
+                None => return None
+            };
+        }
+        None
+    }
+}
+
+/// The state of an external file when it was pinned, see `Builder::pin`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pin {
+    /// A hash of the contents of the file, 0 if unknown
+    pub hash: u64,
+    pub revision: u64
+}
+
+/// What to do when an external file has changed since it was pinned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// Fail with `Error::PinMismatch`
+    Strict,
+    /// Use the file anyway, the mismatch is returned to the caller
+    /// as a warning
+    Warn,
+    /// Do not check the pins
+    Ignore
+}
+
+/// Check the parent of every entity, `parents` is indexed by entity
+fn validate_parents(parents: &[Option<u32>]) -> Vec<Invalid> {
+    let mut invalid = Vec::new();
+    // 0 if the entity has not been visited, 1 if it's on the chain being
+    // walked and 2 if it's ancestors have been checked
+    let mut state = vec![0u8; parents.len()];
+    for i in 0..parents.len() {
+        let mut chain = Vec::new();
+        let mut e = i;
+        loop {
+            if state[e] == 2 {
+                break;
+            }
+            if state[e] == 1 {
+                invalid.push(Invalid::ParentCycle(e as u32));
+                break;
+            }
+            state[e] = 1;
+            chain.push(e);
+            match parents[e] {
+                Some(p) if (p as usize) < parents.len() => e = p as usize,
+                Some(p) => {
+                    invalid.push(Invalid::ParentOutOfRange { entity: e as u32, parent: p });
+                    break;
+                }
+                None => break
+            }
+        }
+        for e in chain {
+            state[e] = 2;
+        }
+    }
+    invalid
+}
+
+/// Walk the linked list of columns looking for a column named `name`
+fn find_column<'a>(root: hairball_capnp::hairball::Reader<'a>, name: &str) -> Option<hairball_capnp::column::Reader<'a>> {
+    let mut column = match root.get_columns() {
+        Ok(c) => c,
+        Err(_) => return None
+    };
+
+    loop {
+        // found a column that is named ans matches outs
+        match column.borrow().get_name() {
+            Err(_) => return None,
+            Ok(v) => {
+                if &v[..] == name {
+                    break
+                }
+            }
+        }
+
+        if !column.has_next() {
+            return None;
+        }
+
+        column = match column.get_next() {
+            Ok(x) => x,
+            Err(_) => return None
+        };
+    }
+
+    Some(column)
+}
+
+fn reader_options() -> capnp::message::ReaderOptions {
+    let mut opts = capnp::message::ReaderOptions::new();
+    opts.traversal_limit_in_words = !0;
+    opts.nesting_limit = 2_000_000_000;
+    opts
+}
+
+// `Reader` is shared between threads, this stops building if it is not `Sync`
+#[allow(dead_code)]
+fn assert_reader_sync() {
+    fn assert_sync<T: Sync>() {}
+    assert_sync::<Reader>();
 }
 
 pub struct Reader {
     uuid: uuid::Uuid,
-    reader: capnp::message::Reader<container::Container>,
+    container: Arc<container::Container>,
+    reader: capnp::message::Reader<container::Segments>,
+    // Readers for every message, indexed by message. Creating one does
+    // not map any segments, they are mapped when a column is accessed.
+    columns: Vec<capnp::message::Reader<container::Segments>>,
+    // built the first time an entity is found by path or guid
+    index: Mutex<Option<EntityIndex>>
+}
+
+/// The first local entity with each path and guid, see `Reader::lookup`
+/// and `Reader::find_guid`
+struct EntityIndex {
+    paths: HashMap<String, usize>,
+    guids: HashMap<uuid::Uuid, usize>
 }
 
 impl Reader {
-    /// Read a `Hairball` from a reader
+    /// Read a `Hairball` from a reader, segments are only mapped
+    /// when they are first accessed
     pub fn read<P>(p: P) -> Result<Reader, Error>
         where P: AsRef<std::path::Path>
     {
-        let mut opts = capnp::message::ReaderOptions::new();
-        opts.traversal_limit_in_words = !0;
-        opts.nesting_limit = 2_000_000_000;
-        container::Container::read(p)
-            .map(|r| Reader{
-                uuid: r.uuid(),
-                reader: capnp::message::Reader::new(r, opts)
-            })
+        let container = Arc::new(try!(container::Container::read(p)));
+        let messages = container.messages_len();
+        Ok(Reader{
+            uuid: container.uuid(),
+            reader: capnp::message::Reader::new(
+                container::Segments::new(container.clone(), 0),
+                reader_options()
+            ),
+            columns: (0..messages).map(|message| {
+                capnp::message::Reader::new(
+                    container::Segments::new(container.clone(), message as u32),
+                    reader_options()
+                )
+            }).collect(),
+            container: container,
+            index: Mutex::new(None)
+        })
     }
 
     /// Get the number of entities
@@ -379,6 +1477,72 @@ impl Reader {
             })
     }
 
+    /// Get the path of an entity, this is the name of each of it's parents
+    /// joined with a `/`. Returns None if the entity or one of it's parents
+    /// is anonymous or external.
+    pub fn path(&self, idx: usize) -> Option<String> {
+        let mut names = Vec::new();
+        let mut next = Some(idx as u32);
+        while let Some(idx) = next {
+            // a parent list longer then the entity table must have a cycle
+            if names.len() > self.entities_len() {
+                return None;
+            }
+
+            let entity = match self.get_entity(idx as usize) {
+                Some(Entity::Local(e)) => e,
+                _ => return None
+            };
+            match entity.name {
+                Some(name) => names.push(name),
+                None => return None
+            }
+            next = entity.parent;
+        }
+
+        names.reverse();
+        Some(names.join("/"))
+    }
+
+    /// internal function that calls `f` with the index of the entities,
+    /// the index is built by the first call
+    fn with_index<T, F>(&self, f: F) -> T
+        where F: FnOnce(&EntityIndex) -> T
+    {
+        let mut index = self.index.lock().unwrap();
+        if index.is_none() {
+            let mut paths = HashMap::new();
+            let mut guids = HashMap::new();
+            for i in 0..self.entities_len() {
+                if let Some(ref e @ Entity::Local(_)) = self.get_entity(i) {
+                    if let Some(&guid) = e.guid() {
+                        guids.entry(guid).or_insert(i);
+                    }
+                }
+                if let Some(path) = self.path(i) {
+                    paths.entry(path).or_insert(i);
+                }
+            }
+            *index = Some(EntityIndex {
+                paths: paths,
+                guids: guids
+            });
+        }
+        f(index.as_ref().unwrap())
+    }
+
+    /// Find the local entity with the supplied path, the paths of every
+    /// entity are indexed by the first lookup
+    pub fn lookup(&self, path: &str) -> Option<usize> {
+        self.with_index(|index| index.paths.get(path).map(|&i| i))
+    }
+
+    /// Find the local entity with the supplied guid, the guids of every
+    /// entity are indexed by the first lookup
+    pub fn find_guid(&self, guid: &uuid::Uuid) -> Option<usize> {
+        self.with_index(|index| index.guids.get(guid).map(|&i| i))
+    }
+
     /// Get the number of external references
     pub fn external_len(&self) -> usize {
         self.reader.get_root::<hairball_capnp::hairball::Reader>()
@@ -406,65 +1570,538 @@ impl Reader {
         self.uuid
     }
 
+    /// Get the version of the library that wrote the file
+    pub fn version(&self) -> [u32; 3] {
+        self.container.version()
+    }
+
+    /// Get the segments that make up the file, see `SegmentInfo`
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        self.container.segments()
+    }
+
+    /// Get the revision the file was written with
+    pub fn revision(&self) -> u64 {
+        self.reader.get_root::<hairball_capnp::hairball::Reader>()
+            .map(|root| root.get_revision())
+            .unwrap_or(0)
+    }
+
+    /// Hash the contents of the file, this reads the entire file.
+    ///
+    /// The hash is the 64 bit FNV-1a of the bytes of every segment of
+    /// every message, in the order of the message table. The segments are
+    /// hashed whole so the zeroed padding at the end of a segment is
+    /// included. The header, the segment and message tables and segments
+    /// that belong to no message are not hashed.
+    pub fn content_hash(&self) -> Result<u64, Error> {
+        use std::hash::Hasher;
+
+        let mut hasher = Fnv1a::new();
+        try!(self.container.hash(&mut hasher));
+        Ok(hasher.finish())
+    }
+
+    /// Get the pin of an external file, returns None if the
+    /// file was not pinned
+    pub fn pin(&self, idx: usize) -> Option<Pin> {
+        self.reader.get_root::<hairball_capnp::hairball::Reader>().ok()
+            .and_then(|root| root.get_pins().ok())
+            .and_then(|pins| {
+                if (pins.len() as usize) <= idx {
+                    None
+                } else {
+                    let p = pins.get(idx as u32);
+                    Some(Pin {
+                        hash: p.get_hash(),
+                        revision: p.get_revision()
+                    })
+                }
+            })
+            .and_then(|p| if p == (Pin { hash: 0, revision: 0 }) { None } else { Some(p) })
+    }
+
+    /// Check if `target` is unchanged since it was pinned by this file,
+    /// this is true if this file did not pin `target`.
+    pub fn pin_matches(&self, target: &Reader) -> Result<bool, Error> {
+        let pin = (0..self.external_len())
+            .find(|&i| self.external(i) == Some(target.uuid()))
+            .and_then(|i| self.pin(i));
+
+        let pin = match pin {
+            Some(pin) => pin,
+            None => return Ok(true)
+        };
+
+        if pin.revision != target.revision() {
+            return Ok(false);
+        }
+        Ok(pin.hash == 0 || pin.hash == try!(target.content_hash()))
+    }
+
+    /// Check the pin of `target` using `policy`. With `Policy::Warn` a
+    /// mismatch is returned as `Ok(Some(Error::PinMismatch))`.
+    pub fn check_pin(&self, target: &Reader, policy: Policy) -> Result<Option<Error>, Error> {
+        match policy {
+            Policy::Ignore => Ok(None),
+            Policy::Warn => {
+                if try!(self.pin_matches(target)) {
+                    Ok(None)
+                } else {
+                    Ok(Some(Error::PinMismatch(target.uuid())))
+                }
+            }
+            Policy::Strict => {
+                if try!(self.pin_matches(target)) {
+                    Ok(None)
+                } else {
+                    Err(Error::PinMismatch(target.uuid()))
+                }
+            }
+        }
+    }
+
+    /// Get the entry in the column table of every chunk of a column
+    fn column_entries(&self, name: &str) -> Vec<hairball_capnp::column::Reader> {
+        let mut entries = Vec::new();
+        let mut column = self.reader.get_root::<hairball_capnp::hairball::Reader>()
+            .and_then(|root| root.get_columns()).ok();
+
+        while let Some(c) = column {
+            if c.get_name().map(|n| n == name).unwrap_or(false) {
+                entries.push(c);
+            }
+            column = if c.has_next() { c.get_next().ok() } else { None };
+        }
+        entries
+    }
+
+    /// Get the root of a column message
+    fn message_root(&self, message: u32) -> Option<capnp::any_pointer::Reader> {
+        self.columns.get(message as usize)
+            .and_then(|reader| reader.get_root().ok())
+    }
+
     /// fetch a column with the name, returns None if not column was found
-    /// that matches the name
+    /// that matches the name. If the column was written in chunks this is
+    /// the first chunk, see `chunks`
     pub fn column(&self, name: &str) -> Option<capnp::any_pointer::Reader> {
-        let root = self.reader.get_root::<hairball_capnp::hairball::Reader>().unwrap();
-        let mut column = match root.get_columns() {
-            Ok(c) => c,
+        let column = self.reader.get_root::<hairball_capnp::hairball::Reader>().ok()
+            .and_then(|root| find_column(root, name));
+
+        match column {
+            Some(c) if c.get_message() != 0 => self.message_root(c.get_message()),
+            Some(c) if c.has_data() => Some(c.get_data()),
+            _ => None
+        }
+    }
+
+    /// Get every chunk of a column in the order they were written, a
+    /// column that was not written in chunks has a single chunk
+    pub fn chunks(&self, name: &str) -> Vec<Chunk> {
+        self.column_entries(name).into_iter().filter_map(|c| {
+            let sorted = c.get_sorted();
+            let schema = c.get_schema();
+            let data = match c.get_message() {
+                0 if c.has_data() => Some(c.get_data()),
+                0 => None,
+                message => self.message_root(message)
+            };
+            data.map(|data| {
+                Chunk {
+                    data: data,
+                    sorted: sorted,
+                    schema: schema
+                }
+            })
+        }).collect()
+    }
+
+    /// Get the capnp type id of the struct the column is stored as,
+    /// None if it was not recorded
+    pub fn column_schema(&self, name: &str) -> Option<u64> {
+        self.column_entries(name).iter()
+            .map(|c| c.get_schema())
+            .find(|&id| id != 0)
+    }
+
+    /// Get the serialized CodeGeneratorRequest that describes the type
+    /// `id` if it was embedded in the file
+    pub fn schema(&self, id: u64) -> Option<&[u8]> {
+        let root = match self.reader.get_root::<hairball_capnp::hairball::Reader>() {
+            Ok(root) => root,
             Err(_) => return None
         };
+        let (ids, schemas) = match (root.get_schema_ids(), root.get_schemas()) {
+            (Ok(ids), Ok(schemas)) => (ids, schemas),
+            _ => return None
+        };
 
-        loop {
-            // found a column that is named ans matches outs
-            match column.borrow().get_name() {
-                Err(_) => return None,
-                Ok(v) => {
-                    if &v[..] == name {
-                        break
+        for i in 0..std::cmp::min(ids.len(), schemas.len()) {
+            if ids.get(i) == id {
+                return schemas.get(i).ok();
+            }
+        }
+        None
+    }
+
+    /// Attribute the words of the file to the entities, the schemas and
+    /// each column. This reads every column of the file.
+    pub fn stats(&self) -> Result<Stats, Error> {
+        // segments that were freed by `Builder::remove_column` and never
+        // reused belong to no message
+        let mut allocated = vec![0; self.container.messages_len()];
+        let mut unused = 0;
+        for s in self.container.segments() {
+            match s.message {
+                Some(message) => allocated[message as usize] += s.size / 8,
+                None => unused += s.size / 8
+            }
+        }
+        let mut referenced = vec![false; allocated.len()];
+        referenced[0] = true;
+
+        let root = try!(self.reader.get_root::<hairball_capnp::hairball::Reader>());
+
+        // the tag word of the list and each entity
+        let list = try!(root.get_entities());
+        let mut entities = 1;
+        let mut names = 0;
+        for i in 0..list.len() {
+            entities += try!(list.get(i).total_size()).word_count;
+        }
+        for i in 0..self.entities_len() {
+            if let Some(name) = self.get_entity(i).and_then(|e| e.name().map(|n| n.len())) {
+                names += text_words(name);
+            }
+        }
+        entities -= names;
+
+        // a pointer to each schema and it's bytes
+        let mut schemas = 0;
+        if root.has_schemas() {
+            let list = try!(root.get_schemas());
+            schemas += list.len() as u64;
+            for i in 0..list.len() {
+                schemas += (try!(list.get(i)).len() as u64 + 7) / 8;
+            }
+        }
+
+        // a column in it's own message uses the whole message, including
+        // the root pointer, the unused words of the message are padding
+        let mut padding = 0;
+        let mut inline = 0;
+        let mut columns = Vec::new();
+        for name in self.column_names() {
+            let mut words = 0;
+            for c in self.column_entries(name) {
+                match c.get_message() {
+                    0 if c.has_data() => {
+                        let size = try!(c.get_data().total_size()).word_count;
+                        inline += size;
+                        words += size;
+                    }
+                    0 => (),
+                    message => {
+                        if let Some(r) = referenced.get_mut(message as usize) {
+                            *r = true;
+                        }
+                        let used = match self.message_root(message) {
+                            Some(data) => try!(data.total_size()).word_count + 1,
+                            None => 0
+                        };
+                        let total = allocated.get(message as usize).cloned().unwrap_or(0);
+                        words += std::cmp::min(used, total);
+                        padding += total.saturating_sub(used);
                     }
                 }
             }
+            columns.push((name.to_owned(), words));
+        }
 
-            if column.has_name() {
-                return None;
+        let used = try!(root.total_size()).word_count + 1;
+        padding += allocated[0].saturating_sub(used);
+        let tables = std::cmp::min(used, allocated[0])
+            .saturating_sub(entities + names + schemas + inline);
+
+        let segments = allocated.iter().fold(unused, |a, b| a + b);
+
+        // messages of removed columns and of `ColumnWriter`s that were
+        // never committed are not referenced by the column table
+        for (message, &words) in allocated.iter().enumerate() {
+            if !referenced[message] {
+                unused += words;
             }
+        }
+
+        Ok(Stats {
+            segments: segments,
+            entities: entities,
+            names: names,
+            schemas: schemas,
+            columns: columns,
+            tables: tables,
+            padding: padding,
+            unused: unused
+        })
+    }
 
+    /// Check if the rows of the column are sorted by the entity they
+    /// belong to, see `Builder::set_sorted`
+    pub fn column_sorted(&self, name: &str) -> bool {
+        self.reader.get_root::<hairball_capnp::hairball::Reader>().ok()
+            .and_then(|root| find_column(root, name))
+            .map(|c| c.get_sorted())
+            .unwrap_or(false)
+    }
 
-            column = match column.get_next() {
-                Ok(x) => x,
-                Err(_) => return None
-            };
+    /// Get the names of every column in the hairball, the tombstones
+    /// of a column are not included, see `tombstoned_columns`
+    pub fn column_names(&self) -> Vec<&str> {
+        self.all_column_names().into_iter()
+            .filter(|name| !name.ends_with(TOMBSTONE_SUFFIX))
+            .collect()
+    }
+
+    /// Get the names of the columns that have tombstones, see
+    /// `Builder::delete_rows`. The column itself may not exist.
+    pub fn tombstoned_columns(&self) -> Vec<&str> {
+        self.all_column_names().into_iter()
+            .filter(|name| name.ends_with(TOMBSTONE_SUFFIX))
+            .map(|name| &name[..name.len() - TOMBSTONE_SUFFIX.len()])
+            .collect()
+    }
+
+    /// internal function that lists every entry of the column table
+    fn all_column_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut column = self.reader.get_root::<hairball_capnp::hairball::Reader>()
+            .and_then(|root| root.get_columns()).ok();
+
+        while let Some(c) = column {
+            if let Ok(name) = c.get_name() {
+                // a column that was written in chunks has an entry for each
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            column = if c.has_next() { c.get_next().ok() } else { None };
         }
+        names
+    }
 
-        if column.has_data() {
-            Some(column.get_data())
-        } else {
-            None
+    /// Advise the OS that the segments backing the column will be needed
+    /// soon. Columns stored in their own message have every segment of the
+    /// message prefetched, inline columns are walked using a separate message
+    /// reader to record which segments they touch. Returns false if the
+    /// column was not found.
+    pub fn prefetch(&self, name: &str) -> bool {
+        let messages: Vec<u32> = self.column_entries(name).iter()
+            .map(|c| c.get_message())
+            .collect();
+        if messages.len() == 0 {
+            return false;
+        }
+        for &message in messages.iter().filter(|&&m| m != 0) {
+            self.container.prefetch_message(message);
+        }
+        if !messages.contains(&0) {
+            return true;
         }
+
+        let touched = RefCell::new(Vec::new());
+        {
+            let reader = capnp::message::Reader::new(
+                container::Recorder::new(&self.container, 0, &touched),
+                reader_options()
+            );
+
+            let column = reader.get_root::<hairball_capnp::hairball::Reader>().ok()
+                .and_then(|root| find_column(root, name));
+            match column {
+                Some(c) => { let _ = c.get_data().total_size(); }
+                None => return false
+            }
+        }
+
+        let mut touched = touched.into_inner();
+        touched.sort();
+        touched.dedup();
+        for id in touched {
+            self.container.prefetch(id);
+        }
+        true
     }
 
-    /// Create a 
+    /// Create a mapping from the entities in this hairball to `E`,
+    /// `f` is called once for each entity index
     pub fn into_mapping<E, F>(&self, mut f: F) -> ReaderMapping<E>
         where F: FnMut(usize) -> E
     {
-        let e: Vec<E> = (0..self.entities_len()).map(&mut f).collect();
+        self.into_sparse_mapping(|i| Some(f(i)))
+    }
 
+    /// Create a mapping where only some of the entities are mapped, rows
+    /// that belong to an entity that `f` returned None for are skipped
+    /// by the column readers
+    pub fn into_sparse_mapping<E, F>(&self, f: F) -> ReaderMapping<E>
+        where F: FnMut(usize) -> Option<E>
+    {
         ReaderMapping {
             reader: self,
-            entities: e
+            entities: (0..self.entities_len()).map(f).collect(),
+            origins: RefCell::new(None)
         }
     }
+
+    /// Create a mapping where `f` is called with the index of each entity,
+    /// the decoded entity, the mapping of it's parent and the entity that
+    /// an external entity links to in `library`. Parents are always mapped
+    /// before their children. Entities that `f` returns None for are not
+    /// mapped, this includes the children of an entity that is not mapped.
+    pub fn into_entity_mapping<'a, E, F>(&'a self, library: Option<&'a Library>, mut f: F) -> ReaderMapping<'a, E>
+        where F: FnMut(usize, Entity<&str>, Option<&E>, Option<Link>) -> Option<E>
+    {
+        let len = self.entities_len();
+        let mut entities: Vec<Option<E>> = (0..len).map(|_| None).collect();
+        // 0 if the entity has not been visited, 1 if it's parents are being
+        // mapped and 2 if it has been mapped
+        let mut state = vec![0u8; len];
+
+        for i in 0..len {
+            let mut stack = vec![i];
+            while let Some(&j) = stack.last() {
+                if state[j] == 2 {
+                    stack.pop();
+                    continue;
+                }
+
+                let entity = self.get_entity(j).unwrap();
+                let parent = match entity.parent() {
+                    Some(p) if (p as usize) < len => Some(p as usize),
+                    _ => None
+                };
+
+                // a parent that is being mapped is part of a cycle
+                if let Some(p) = parent {
+                    if state[p] == 0 {
+                        state[j] = 1;
+                        stack.push(p);
+                        continue;
+                    }
+                }
+
+                let target = match (&entity, library) {
+                    (&Entity::External(_), Some(library)) => library.resolve(self, j as u32),
+                    _ => None
+                };
+
+                let e = {
+                    let parent = parent.and_then(|p| entities[p].as_ref());
+                    match (parent, entity.parent()) {
+                        (None, Some(_)) => None,
+                        (parent, _) => f(j, entity, parent, target)
+                    }
+                };
+                entities[j] = e;
+                state[j] = 2;
+                stack.pop();
+            }
+        }
+
+        ReaderMapping {
+            reader: self,
+            entities: entities,
+            origins: RefCell::new(None)
+        }
+    }
+
+    /// Check that every parent is an entity, that there are no cycles
+    /// in the parents and that the tombstones only reference entities
+    /// that exist. The rows of other columns are checked by the crate
+    /// that reads the column.
+    pub fn validate(&self) -> Vec<Invalid> {
+        let len = self.entities_len();
+        let parents: Vec<Option<u32>> = (0..len)
+            .map(|i| self.get_entity(i).and_then(|e| e.parent()))
+            .collect();
+        let mut invalid = validate_parents(&parents[..]);
+
+        for column in self.tombstoned_columns() {
+            for (row, entity) in self.tombstones(column).into_iter().enumerate() {
+                if entity as usize >= len {
+                    invalid.push(Invalid::DanglingReference {
+                        column: tombstone_column(column),
+                        row: row as u32,
+                        entity: entity
+                    });
+                }
+            }
+        }
+        invalid
+    }
+
+    /// Get the entities whose rows in `column` were deleted, see
+    /// `Builder::delete_rows`
+    pub fn tombstones(&self, column: &str) -> Vec<u32> {
+        self.column(&tombstone_column(column))
+            .and_then(|c| c.get_as::<capnp::primitive_list::Reader<u32>>().ok())
+            .map(|list| (0..list.len()).map(|i| list.get(i)).collect())
+            .unwrap_or(Vec::new())
+    }
 }
 
 pub struct ReaderMapping<'a, E> {
     reader: &'a Reader,
-    entities: Vec<E>
+    entities: Vec<Option<E>>,
+    // the first entity each `E` is mapped to, this is built the first
+    // time `Origin::origin` is called and is reset by `link`
+    origins: RefCell<Option<HashMap<E, usize>>>
 }
 
 impl<'a, E> ReaderMapping<'a, E> {
     pub fn entity(&self, i: usize) -> Option<&E> {
-        self.entities.get(i)
+        self.entities.get(i).and_then(|e| e.as_ref())
+    }
+
+    /// Get the mapping of an entity referenced by a row of a column,
+    /// this is an error if the entity does not exist
+    pub fn row_entity(&self, i: u32) -> Result<Option<&E>, RowCause> {
+        match self.entities.get(i as usize) {
+            Some(e) => Ok(e.as_ref()),
+            None => Err(RowCause::EntityOutOfRange(i))
+        }
+    }
+
+    /// Map the external entities that reference the hairball of `other`
+    /// to the same `E` that `other` mapped the entity to, this replaces
+    /// any existing mapping of the external entity. This is used when
+    /// the hairball of `other` has already been loaded.
+    pub fn link(&mut self, other: &ReaderMapping<E>)
+        where E: Clone
+    {
+        let reader = self.reader;
+        let target = other.reader;
+        for i in 0..self.entities.len() {
+            let idx = match reader.get_entity(i) {
+                Some(e @ Entity::External(_)) => {
+                    if e.file() != Some(&target.uuid()) {
+                        continue;
+                    }
+                    match (e.name(), e.guid()) {
+                        (Some(name), _) => target.lookup(name),
+                        (None, Some(guid)) => target.find_guid(guid),
+                        (None, None) => None
+                    }
+                }
+                _ => None
+            };
+
+            if let Some(e) = idx.and_then(|idx| other.entity(idx)) {
+                self.entities[i] = Some(e.clone());
+            }
+        }
+        *self.origins.borrow_mut() = None;
     }
 }
 
@@ -472,3 +2109,291 @@ impl<'a, E> std::ops::Deref for ReaderMapping<'a, E> {
     type Target = Reader;
     fn deref(&self) -> &Reader { self.reader }
 }
+
+/// Get the order the rows of a column are written in so they are
+/// sorted by the entity they belong to, see `Builder::set_sorted`.
+/// Rows of the same entity stay in the order they were supplied.
+pub fn sorted_order<T, F>(rows: &[T], id: F) -> Vec<usize>
+    where F: Fn(&T) -> u32
+{
+    let mut order: Vec<usize> = (0..rows.len()).collect();
+    order.sort_by(|&a, &b| id(&rows[a]).cmp(&id(&rows[b])));
+    order
+}
+
+/// The 64 bit FNV-1a hash, used by `Reader::content_hash` since the
+/// value is stored in pins and must not change between releases
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl std::hash::Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Find the rows that belong to `entity` in a column that is sorted by
+/// entity, `id` gets the entity of a row
+pub fn sorted_rows<F>(len: u32, entity: u32, id: F) -> std::ops::Range<u32>
+    where F: Fn(u32) -> u32
+{
+    // the first row whose entity is after `entity`, or is not before
+    // it if `inclusive` is false
+    let bound = |inclusive: bool| {
+        let (mut low, mut high) = (0, len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let e = id(mid);
+            if e < entity || (inclusive && e == entity) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    };
+    bound(false)..bound(true)
+}
+
+/// Added to the name of a column to get the column that holds it's tombstones
+const TOMBSTONE_SUFFIX: &'static str = ".tombstone";
+
+/// The name of the column that holds the tombstones for `column`
+fn tombstone_column(column: &str) -> String {
+    format!("{}{}", column, TOMBSTONE_SUFFIX)
+}
+
+/// The number of words a text of `len` bytes uses, including it's
+/// nul terminator
+fn text_words(len: usize) -> u64 {
+    (len as u64 + 8) / 8
+}
+
+/// Where the words of a hairball are used, see `Reader::stats`. Every
+/// size is in capnp words of 8 bytes, the sizes add up to `segments`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// The size of every segment in the file
+    pub segments: u64,
+    /// The entity table, excluding the names of the entities
+    pub entities: u64,
+    /// The names of local entities and the paths of external entities
+    pub names: u64,
+    /// The embedded schemas
+    pub schemas: u64,
+    /// The words of each column in the order of `column_names`, this
+    /// includes every chunk of the column
+    pub columns: Vec<(String, u64)>,
+    /// The rest of the root message, such as the column table, the
+    /// external file table and the pins
+    pub tables: u64,
+    /// Words of a segment that were never written, the container
+    /// allocates segments in multiples of 4096 bytes
+    pub padding: u64,
+    /// Segments that no column uses, such as those of a removed column
+    /// that were not reused, `hairball_tools::repack` drops them
+    pub unused: u64
+}
+
+/// A chunk of a column, see `Reader::chunks`
+pub struct Chunk<'a> {
+    pub data: capnp::any_pointer::Reader<'a>,
+    /// true if the rows of the chunk are sorted by entity
+    pub sorted: bool,
+    /// the type id of the chunk's data, 0 if unknown
+    pub schema: u64
+}
+
+/// A stack of hairballs that are read as a single hairball, such as a
+/// base scene and a save file. Entities are matched between the layers
+/// using their path, the rows of a later layer replace the rows of the
+/// same entity in earlier layers. A layer can also delete the rows of an
+/// earlier layer, see `Builder::delete_rows`.
+pub struct Overlay {
+    layers: Vec<Reader>,
+    // pins that did not match, see `Policy::Warn`
+    warnings: Vec<Error>
+}
+
+impl Overlay {
+    /// Create an overlay, the first layer is the base
+    pub fn new(layers: Vec<Reader>) -> Overlay {
+        Overlay {
+            layers: layers,
+            warnings: Vec::new()
+        }
+    }
+
+    /// Create an overlay, checking the pins that each layer has on
+    /// the other layers using `policy`
+    pub fn with_policy(layers: Vec<Reader>, policy: Policy) -> Result<Overlay, Error> {
+        let mut warnings = Vec::new();
+        for a in &layers {
+            for b in &layers {
+                if a.uuid() != b.uuid() {
+                    if let Some(warning) = try!(a.check_pin(b, policy)) {
+                        warnings.push(warning);
+                    }
+                }
+            }
+        }
+        Ok(Overlay {
+            layers: layers,
+            warnings: warnings
+        })
+    }
+
+    /// The pins that did not match when the overlay was created
+    /// with `Policy::Warn`
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings[..]
+    }
+
+    /// Get the layers of the overlay
+    pub fn layers(&self) -> &[Reader] {
+        &self.layers[..]
+    }
+
+    /// Create a mapping from the entities of the overlay to `E`, `f`
+    /// is called once for each unique path. An external entity that points
+    /// into another layer is the same entity as the one it points at.
+    /// Anonymous entities are not mapped.
+    pub fn into_mapping<E, F>(&self, mut f: F) -> OverlayMapping<E>
+        where F: FnMut(&str) -> E
+    {
+        let mut slots: HashMap<String, usize> = HashMap::new();
+        let mut entities = Vec::new();
+        let mut layers = Vec::new();
+
+        for r in &self.layers {
+            let mut mapping = Vec::new();
+            for i in 0..r.entities_len() {
+                let path = match r.get_entity(i) {
+                    Some(Entity::Local(_)) => r.path(i),
+                    Some(ref e) => {
+                        e.file()
+                         .and_then(|f| self.layers.iter().find(|l| &l.uuid() == f))
+                         .and_then(|target| {
+                            match (e.name(), e.guid()) {
+                                (Some(name), _) => Some(name.to_owned()),
+                                (None, Some(guid)) => target.find_guid(guid).and_then(|i| target.path(i)),
+                                (None, None) => None
+                            }
+                         })
+                    }
+                    None => None
+                };
+
+                mapping.push(path.map(|path| {
+                    if let Some(&slot) = slots.get(&path) {
+                        return slot;
+                    }
+                    entities.push(f(&path));
+                    slots.insert(path, entities.len() - 1);
+                    entities.len() - 1
+                }));
+            }
+            layers.push(ReaderMapping {
+                reader: r,
+                entities: mapping,
+                origins: RefCell::new(None)
+            });
+        }
+
+        OverlayMapping {
+            entities: entities,
+            layers: layers
+        }
+    }
+}
+
+/// Maps the entities of an `Overlay`, each layer has a `ReaderMapping`
+/// from it's entities to the index of the entity in the overlay.
+pub struct OverlayMapping<'a, E> {
+    entities: Vec<E>,
+    layers: Vec<ReaderMapping<'a, usize>>
+}
+
+impl<'a, E> OverlayMapping<'a, E> {
+    /// Get an entity by it's index in the overlay
+    pub fn entity(&self, i: usize) -> Option<&E> {
+        self.entities.get(i)
+    }
+
+    /// Get the mappings of each layer
+    pub fn layers(&self) -> &[ReaderMapping<'a, usize>] {
+        &self.layers[..]
+    }
+
+    /// Resolve a column across every layer. `read` is called with each
+    /// layer and returns the rows of the column in that layer, keyed by
+    /// the index of the entity in the overlay. Returns None if no layer
+    /// had the column.
+    pub fn column<T, F>(&'a self, name: &str, mut read: F) -> Option<OverlayColumn<'a, E, T>>
+        where F: FnMut(&'a ReaderMapping<'a, usize>) -> Option<Box<Iterator<Item=(usize, T)> + 'a>>
+    {
+        let mut found = false;
+        let mut rows: Vec<Vec<T>> = (0..self.entities.len()).map(|_| Vec::new()).collect();
+
+        for layer in &self.layers {
+            for id in layer.tombstones(name) {
+                if let Some(&slot) = layer.entity(id as usize) {
+                    rows[slot].clear();
+                }
+            }
+
+            if let Some(iter) = read(layer) {
+                found = true;
+                // the first row of an entity in this layer replaces
+                // any rows from earlier layers
+                let mut replaced = vec![false; rows.len()];
+                for (slot, row) in iter {
+                    if !replaced[slot] {
+                        replaced[slot] = true;
+                        rows[slot].clear();
+                    }
+                    rows[slot].push(row);
+                }
+            }
+        }
+
+        if !found {
+            return None;
+        }
+
+        let mut resolved = Vec::new();
+        for (slot, r) in rows.into_iter().enumerate() {
+            resolved.extend(r.into_iter().map(|row| (slot, row)));
+        }
+
+        Some(OverlayColumn {
+            mapping: self,
+            rows: resolved.into_iter()
+        })
+    }
+}
+
+/// The resolved rows of a column in an `Overlay`, see `OverlayMapping::column`
+pub struct OverlayColumn<'a, E: 'a, T> {
+    mapping: &'a OverlayMapping<'a, E>,
+    rows: std::vec::IntoIter<(usize, T)>
+}
+
+impl<'a, E, T> Iterator for OverlayColumn<'a, E, T> {
+    type Item = (&'a E, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next().map(|(slot, row)| (&self.mapping.entities[slot], row))
+    }
+}
@@ -1,4 +1,5 @@
 extern crate capnp;
+#[macro_use]
 extern crate hairball;
 
 #[cfg(feature="build-schema")]
@@ -9,7 +10,20 @@ pub mod material_capnp {
 #[cfg(not(feature="build-schema"))]
 pub mod material_capnp;
 
-const COLUMN_NAME: &'static str = "material";
+/// The name the column is stored under in a hairball
+pub const COLUMN_NAME: &'static str = "material";
+
+/// The schema of the column as a serialized CodeGeneratorRequest, this is
+/// only available if the crate was built with `build-schema`
+#[cfg(feature="build-schema")]
+pub fn schema() -> Option<&'static [u8]> {
+    Some(include_bytes!(concat!(env!("OUT_DIR"), "/material.schema")))
+}
+
+#[cfg(not(feature="build-schema"))]
+pub fn schema() -> Option<&'static [u8]> {
+    None
+}
 
 #[derive(Copy, Clone, Debug)]
 pub enum Value {
@@ -24,97 +38,127 @@ pub enum Component {
     Specular
 }
 
-pub fn write(hb: &mut hairball::Builder, i: &[(u32, Component, Value)]) {
+pub fn write<C>(hb: &mut C, i: &[(u32, Component, Value)])
+    where C: hairball::Columns
+{
     use material_capnp::Component::*;
 
-    let column: material_capnp::column::Builder = hb.column(COLUMN_NAME)
-        .and_then(|c| c.get_as())
-        .unwrap();
-
-    let mut rows = column.init_bindings(i.len() as u32);
-    for (i, &(name, c, v)) in i.iter().enumerate() {
-        let mut row = rows.borrow().get(i as u32);
-        row.set_id(name);
-        row.set_component(match c {
-            Component::Ambient => Ambient,
-            Component::Diffuse => Diffuse,
-            Component::Specular => Specular,
-        });
-        match v {
-            Value::Texture(t) => {
-                row.set_texture(t)
-            }
-            Value::Color(c) => {
-                let mut color = row.init_color();
-                color.set_red(c[0]);
-                color.set_green(c[1]);
-                color.set_blue(c[2]);
-                color.set_alpha(c[3]);
+    let order = hairball::sorted_order(i, |r| r.0);
+    {
+        let column: material_capnp::column::Builder = hb.append_rows(COLUMN_NAME, i.len() as u32)
+            .and_then(|c| c.get_as())
+            .unwrap();
+
+        let mut rows = column.init_bindings(i.len() as u32);
+        for (n, &idx) in order.iter().enumerate() {
+            let (name, c, v) = i[idx];
+            let mut row = rows.borrow().get(n as u32);
+            row.set_id(name);
+            row.set_component(match c {
+                Component::Ambient => Ambient,
+                Component::Diffuse => Diffuse,
+                Component::Specular => Specular,
+            });
+            match v {
+                Value::Texture(t) => {
+                    row.set_texture(t)
+                }
+                Value::Color(c) => {
+                    let mut color = row.init_color();
+                    color.set_red(c[0]);
+                    color.set_green(c[1]);
+                    color.set_blue(c[2]);
+                    color.set_alpha(c[3]);
+                }
             }
         }
     }
+    for (row, &idx) in order.iter().enumerate() {
+        hb.reference(COLUMN_NAME, row as u32, i[idx].0);
+    }
+    hb.set_sorted(COLUMN_NAME, true);
+    hb.set_schema(COLUMN_NAME,
+                  <material_capnp::column::Reader as capnp::traits::HasTypeId>::type_id(),
+                  schema());
 }
 
-impl<'a, E> Iterator for Reader<'a, E> {
-    type Item = (&'a E, Component, Value);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        use material_capnp::Component::*;
-        use material_capnp::binding::Which;
-
-        if let Ok(meshes) = self.column.get_bindings() {
-            while self.index < meshes.len() {
-                let idx = self.index;
-                self.index += 1;
-
-                let m = meshes.get(idx);
-                let id = match self.reader.entity(m.get_id() as usize) {
-                    Some(id) => id,
-                    None => continue
-                };
-
-                let comp = match m.get_component() {
-                    Ok(Ambient) => Component::Ambient,
-                    Ok(Diffuse) => Component::Diffuse,
-                    Ok(Specular) => Component::Specular,
-                    Err(_) => continue
-                };
-
-                let value = match m.which() {
-                    Ok(Which::Texture(t)) => {
-                        Value::Texture(t)
-                    },
-                    Ok(Which::Color(Ok(c))) => {
-                        Value::Color([
-                            c.get_red(),
-                            c.get_green(),
-                            c.get_blue(),
-                            c.get_alpha()
-                        ])
-                    }
-                    Ok(Which::Color(_)) | Err(_) => continue
-                };
-                return Some((id, comp, value));
-            }
+fn decode(m: material_capnp::binding::Reader) -> Result<(Component, Value), hairball::RowCause> {
+    use material_capnp::Component::*;
+    use material_capnp::binding::Which;
+
+    let comp = match try!(m.get_component()) {
+        Ambient => Component::Ambient,
+        Diffuse => Component::Diffuse,
+        Specular => Component::Specular
+    };
+
+    let value = match try!(m.which()) {
+        Which::Texture(t) => {
+            Value::Texture(t)
+        },
+        Which::Color(c) => {
+            let c = try!(c);
+            Value::Color([
+                c.get_red(),
+                c.get_green(),
+                c.get_blue(),
+                c.get_alpha()
+            ])
         }
-        None
-    }
+    };
+    Ok((comp, value))
 }
 
-pub struct Reader<'a, E:'a> {
-    reader: &'a hairball::ReaderMapping<'a, E>,
-    column: material_capnp::column::Reader<'a>,
-    index: u32
+column_reader! {
+    material_capnp::column.get_bindings[binding],
+    |mapping, row| -> (&'a E, Component, Value) => {
+        let id = match try!(mapping.row_entity(row.get_id())) {
+            Some(id) => id,
+            None => return Ok(None)
+        };
+        let (comp, value) = try!(decode(row));
+        Ok(Some((id, comp, value)))
+    },
+    references |row| [row.get_id()]
 }
 
-pub fn read<'a, E>(read: &'a hairball::ReaderMapping<'a, E>) -> Option<Reader<'a, E>>
+/// Read the column from every layer of an overlay, the rows of an entity
+/// in a later layer replace it's rows in earlier layers
+pub fn read_overlay<'a, E>(overlay: &'a hairball::OverlayMapping<'a, E>)
+    -> Option<Box<Iterator<Item=(&'a E, Component, Value)> + 'a>>
     where E: 'a
 {
-    read.column(COLUMN_NAME)
-        .and_then(|c| c.get_as().ok() )
-        .map(|c| Reader{
-            reader: read,
-            column: c,
-            index: 0
+    overlay.column(COLUMN_NAME, |layer| {
+        read(layer).map(|rows| {
+            Box::new(rows.map(|(&id, c, v)| (id, (c, v))))
+                as Box<Iterator<Item=(usize, (Component, Value))> + 'a>
         })
+    }).map(|rows| {
+        Box::new(rows.map(|(e, (c, v))| (e, c, v)))
+            as Box<Iterator<Item=(&'a E, Component, Value)> + 'a>
+    })
+}
+
+/// Find the material of an entity that was resolved through a `Library`,
+/// sorted chunks are binary searched
+pub fn lookup(link: &hairball::Link) -> Vec<(Component, Value)> {
+    let mut material = Vec::new();
+    for chunk in link.reader.chunks(COLUMN_NAME) {
+        let bindings = chunk.data.get_as::<material_capnp::column::Reader>().ok()
+            .and_then(|c| c.get_bindings().ok());
+        if let Some(bindings) = bindings {
+            let range = if chunk.sorted {
+                hairball::sorted_rows(bindings.len(), link.entity as u32, |i| bindings.get(i).get_id())
+            } else {
+                0..bindings.len()
+            };
+            for i in range {
+                let m = bindings.get(i);
+                if m.get_id() as usize == link.entity {
+                    material.extend(decode(m).ok().into_iter());
+                }
+            }
+        }
+    }
+    material
 }
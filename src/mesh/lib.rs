@@ -1,4 +1,5 @@
 extern crate capnp;
+#[macro_use]
 extern crate hairball;
 extern crate gfx;
 extern crate gfx_mesh;
@@ -13,7 +14,20 @@ pub mod mesh_capnp {
 #[cfg(not(feature="build-schema"))]
 pub mod mesh_capnp;
 
-const COLUMN_NAME: &'static str = "mesh";
+/// The name the column is stored under in a hairball
+pub const COLUMN_NAME: &'static str = "mesh";
+
+/// The schema of the column as a serialized CodeGeneratorRequest, this is
+/// only available if the crate was built with `build-schema`
+#[cfg(feature="build-schema")]
+pub fn schema() -> Option<&'static [u8]> {
+    Some(include_bytes!(concat!(env!("OUT_DIR"), "/mesh.schema")))
+}
+
+#[cfg(not(feature="build-schema"))]
+pub fn schema() -> Option<&'static [u8]> {
+    None
+}
 
 pub enum Error {
     Capnp(capnp::Error),
@@ -39,15 +53,90 @@ impl std::convert::From<gfx_mesh::Error> for Error {
     }
 }
 
+impl std::convert::From<Error> for hairball::RowCause {
+    fn from(err: Error) -> hairball::RowCause {
+        match err {
+            Error::Capnp(err) => hairball::RowCause::Capnp(err),
+            Error::NotInSchema => {
+                hairball::RowCause::Invalid("attribute type is not in the schema".to_owned())
+            }
+            Error::Mesh(_) => {
+                hairball::RowCause::Invalid("vertex data does not match the attributes".to_owned())
+            }
+        }
+    }
+}
+
 pub type Mesh<'a> = Vec<Interlaced<Vec<Attribute<&'a str>>, &'a str, &'a [u8]>>;
 
-fn decode_attribute<'a>(a: mesh_capnp::attribute::Reader<'a>) -> Result<Attribute<&'a str>, Error> {
-    use mesh_capnp::Type::*;
+/// A mesh that owns it's vertex data, see `write_owned`
+pub type OwnedMesh = Vec<Interlaced<Vec<Attribute<String>>, String, Vec<u8>>>;
 
-    let name = try!(a.get_name());
-    let count = a.get_element_count();
+/// The size in bytes of a single vertex with these attributes
+pub fn stride<S>(attributes: &[Attribute<S>]) -> usize {
+    use gfx::device::attrib::{Type, FloatSize, IntSize};
+
+    attributes.iter().map(|a| {
+        let size = match a.element_type {
+            Type::Int(_, IntSize::U8, _) => 1,
+            Type::Int(_, IntSize::U16, _) => 2,
+            Type::Int(_, IntSize::U32, _) => 4,
+            Type::Float(_, FloatSize::F16) => 2,
+            Type::Float(_, FloatSize::F32) => 4,
+            Type::Float(_, FloatSize::F64) => 8,
+            _ => 0
+        };
+        size * a.element_count as usize
+    }).fold(0, |a, b| a + b)
+}
+
+/// The number of vertices stored in a vertex buffer
+pub fn vertex_count<A, S, D>(vb: &Interlaced<A, S, D>) -> usize
+    where A: AsRef<[Attribute<S>]>,
+          D: AsRef<[u8]>
+{
+    match stride(vb.attributes().as_ref()) {
+        0 => 0,
+        stride => vb.data().as_ref().len() / stride
+    }
+}
+
+/// The name of each attribute type, these match the enumerants of the schema
+const TYPE_NAMES: [(mesh_capnp::Type, &'static str); 14] = [
+    (mesh_capnp::Type::F32, "f32"),
+    (mesh_capnp::Type::F64, "f64"),
+    (mesh_capnp::Type::U8, "u8"),
+    (mesh_capnp::Type::U16, "u16"),
+    (mesh_capnp::Type::U32, "u32"),
+    (mesh_capnp::Type::I8, "i8"),
+    (mesh_capnp::Type::I16, "i16"),
+    (mesh_capnp::Type::I32, "i32"),
+    (mesh_capnp::Type::NormalizedU8, "normalizedU8"),
+    (mesh_capnp::Type::NormalizedU16, "normalizedU16"),
+    (mesh_capnp::Type::NormalizedU32, "normalizedU32"),
+    (mesh_capnp::Type::NormalizedI8, "normalizedI8"),
+    (mesh_capnp::Type::NormalizedI16, "normalizedI16"),
+    (mesh_capnp::Type::NormalizedI32, "normalizedI32")
+];
+
+/// The name of the attribute's type, such as `f32` or `normalizedU8`
+pub fn type_name<S>(attribute: &Attribute<S>) -> &'static str {
+    let t = element_type(attribute);
+    TYPE_NAMES.iter().find(|&&(x, _)| x == t).map(|&(_, name)| name).unwrap()
+}
+
+/// Create an attribute from the name of it's type, see `type_name`.
+/// Returns None if the type is not known.
+pub fn attribute<S>(name: S, type_name: &str, count: u8) -> Option<Attribute<S>> {
+    TYPE_NAMES.iter()
+        .find(|&&(_, n)| n == type_name)
+        .map(|&(t, _)| new_attribute(name, t, count))
+}
+
+fn new_attribute<S>(name: S, t: mesh_capnp::Type, count: u8) -> Attribute<S> {
+    use mesh_capnp::Type::*;
 
-    Ok(match try!(a.get_element_type()) {
+    match t {
         F32 => Attribute::f32(name, count),
         F64 => Attribute::f64(name, count),
         U8 => Attribute::u8(name, count),
@@ -62,7 +151,13 @@ fn decode_attribute<'a>(a: mesh_capnp::attribute::Reader<'a>) -> Result<Attribut
         NormalizedI8 => Attribute::normalized_i8(name, count),
         NormalizedI16=> Attribute::normalized_i16(name, count),
         NormalizedI32 => Attribute::normalized_i32(name, count),
-    })
+    }
+}
+
+fn decode_attribute<'a>(a: mesh_capnp::attribute::Reader<'a>) -> Result<Attribute<&'a str>, Error> {
+    let name = try!(a.get_name());
+    let count = a.get_element_count();
+    Ok(new_attribute(name, try!(a.get_element_type()), count))
 }
 
 fn decode_attributes<'a>(r: capnp::struct_list::Reader<'a, mesh_capnp::attribute::Owned>) -> Result<Vec<Attribute<&'a str>>, Error> {
@@ -92,53 +187,21 @@ fn decode<'a>(m: mesh_capnp::mesh::Reader<'a>) -> Result<(usize, Mesh<'a>), Erro
     Ok((id as usize, buffers))
 }
 
-impl<'a, E> Iterator for Reader<'a, E> {
-    type Item = (&'a E, Mesh<'a>);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Ok(meshes) = self.column.get_meshes() {
-            while self.index < meshes.len() {
-                let idx = self.index;
-                self.index += 1;
-
-                if let Ok((idx, m)) = decode(meshes.get(idx)) {
-                    if let Some(e) = self.reader.entity(idx) {
-                        return Some((e, m));
-                    }
-                }
-            }            
-        }
-        None
-    }
-}
-
-pub struct Reader<'a, E:'a> {
-    reader: &'a hairball::ReaderMapping<'a, E>,
-    column: mesh_capnp::column::Reader<'a>,
-    index: u32
-}
-
-pub fn read<'a, E>(read: &'a hairball::ReaderMapping<'a, E>) -> Option<Reader<'a, E>>
-    where E: 'a
-{
-    read.column(COLUMN_NAME)
-        .and_then(|c| c.get_as().ok() )
-        .map(|c| Reader{
-            reader: read,
-            column: c,
-            index: 0
-        })
+column_reader! {
+    mesh_capnp::column.get_meshes[mesh],
+    |mapping, row| -> (&'a E, Mesh<'a>) => {
+        let (id, m) = try!(decode(row));
+        Ok(try!(mapping.row_entity(id as u32)).map(|e| (e, m)))
+    },
+    references |row| [row.get_id()]
 }
 
-fn write_attribute<S>(mut builder: mesh_capnp::attribute::Builder, mesh: &Attribute<S>)
-    where S: AsRef<str>
-{
+/// The schema type of an attribute, this panics if it can not be stored
+fn element_type<S>(mesh: &Attribute<S>) -> mesh_capnp::Type {
     use gfx::device::attrib::{Type, FloatSize, FloatSubType, IntSize, IntSubType, SignFlag};
     use mesh_capnp::Type::*;
 
-    builder.set_name(mesh.name.as_ref());
-    builder.set_element_count(mesh.element_count);
-    let t = match mesh.element_type {
+    match mesh.element_type {
         Type::Float(FloatSubType::Default, FloatSize::F32) => F32,
         Type::Float(FloatSubType::Default, FloatSize::F64) => F64,
         Type::Int(IntSubType::Raw, IntSize::U8, SignFlag::Signed) => I8,
@@ -154,8 +217,15 @@ fn write_attribute<S>(mut builder: mesh_capnp::attribute::Builder, mesh: &Attrib
         Type::Int(IntSubType::Normalized, IntSize::U16, SignFlag::Unsigned) => NormalizedU16,
         Type::Int(IntSubType::Normalized, IntSize::U32, SignFlag::Unsigned) => NormalizedU32,
         _ => panic!("unsupported type {:?}", mesh.element_type)
-    };
-    builder.set_element_type(t);
+    }
+}
+
+fn write_attribute<S>(mut builder: mesh_capnp::attribute::Builder, mesh: &Attribute<S>)
+    where S: AsRef<str>
+{
+    builder.set_name(mesh.name.as_ref());
+    builder.set_element_count(mesh.element_count);
+    builder.set_element_type(element_type(mesh));
 }
 
 fn write_buffers<R, A, S, D>(mut builder: mesh_capnp::mesh::Builder, name: u32, mesh: &R)
@@ -177,20 +247,97 @@ fn write_buffers<R, A, S, D>(mut builder: mesh_capnp::mesh::Builder, name: u32,
     }
 }
 
-///
-pub fn write<'a, R, A, S, D>(hb: &mut hairball::Builder, i: &[(u32, &'a R)])
-    where R: AsRef<[Interlaced<A, S, D>]>,
+/// Append the meshes to the column, rows written by earlier calls are kept
+pub fn write<'a, C, R, A, S, D>(hb: &mut C, i: &[(u32, &'a R)])
+    where C: hairball::Columns,
+          R: AsRef<[Interlaced<A, S, D>]>,
           A: AsRef<[Attribute<S>]>,
           S: AsRef<str>,
           D: AsRef<[u8]>
 {
-    let column: mesh_capnp::column::Builder = hb.column(COLUMN_NAME)
-        .and_then(|c| c.get_as())
-        .unwrap();
-
-    let mut rows = column.init_meshes(i.len() as u32);
-    for (i, &(name, ref mesh)) in i.iter().enumerate() {
-        let row = rows.borrow().get(i as u32);
-        write_buffers(row, name, mesh);
+    let order = hairball::sorted_order(i, |r| r.0);
+    {
+        let column: mesh_capnp::column::Builder = hb.append_rows(COLUMN_NAME, i.len() as u32)
+            .and_then(|c| c.get_as())
+            .unwrap();
+
+        let mut rows = column.init_meshes(i.len() as u32);
+        for (n, &idx) in order.iter().enumerate() {
+            let (name, mesh) = i[idx];
+            let row = rows.borrow().get(n as u32);
+            write_buffers(row, name, mesh);
+        }
+    }
+    for (row, &idx) in order.iter().enumerate() {
+        hb.reference(COLUMN_NAME, row as u32, i[idx].0);
+    }
+    hb.set_sorted(COLUMN_NAME, true);
+    hb.set_schema(COLUMN_NAME,
+                  <mesh_capnp::column::Reader as capnp::traits::HasTypeId>::type_id(),
+                  schema());
+}
+
+/// Write out a column of owned meshes, this can be used as the write
+/// function of a `hairball::ChunkedWriter`
+pub fn write_owned<C>(hb: &mut C, i: &[(u32, OwnedMesh)])
+    where C: hairball::Columns
+{
+    let rows: Vec<(u32, &OwnedMesh)> = i.iter().map(|&(id, ref m)| (id, m)).collect();
+    write(hb, &rows[..]);
+}
+
+/// Read the column from every layer of an overlay, the rows of an entity
+/// in a later layer replace it's rows in earlier layers
+pub fn read_overlay<'a, E>(overlay: &'a hairball::OverlayMapping<'a, E>)
+    -> Option<Box<Iterator<Item=(&'a E, Mesh<'a>)> + 'a>>
+    where E: 'a
+{
+    overlay.column(COLUMN_NAME, |layer| {
+        read(layer).map(|rows| {
+            Box::new(rows.map(|(&id, mesh)| (id, mesh)))
+                as Box<Iterator<Item=(usize, Mesh<'a>)> + 'a>
+        })
+    }).map(|rows| Box::new(rows) as Box<Iterator<Item=(&'a E, Mesh<'a>)> + 'a>)
+}
+
+/// Find the mesh of an entity that was resolved through a `Library`,
+/// sorted chunks are binary searched
+pub fn lookup<'a>(link: &hairball::Link<'a>) -> Option<Mesh<'a>> {
+    for chunk in link.reader.chunks(COLUMN_NAME) {
+        let meshes = chunk.data.get_as::<mesh_capnp::column::Reader>().ok()
+            .and_then(|c| c.get_meshes().ok());
+        if let Some(meshes) = meshes {
+            let range = if chunk.sorted {
+                hairball::sorted_rows(meshes.len(), link.entity as u32, |i| meshes.get(i).get_id())
+            } else {
+                0..meshes.len()
+            };
+            for i in range {
+                let m = meshes.get(i);
+                if m.get_id() as usize == link.entity {
+                    return decode(m).ok().map(|(_, m)| m);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// The words used by each vertex buffer of each row, along with the
+/// entity the row belongs to. A buffer includes it's attributes.
+pub fn stats(reader: &hairball::Reader) -> capnp::Result<Vec<(u32, Vec<u64>)>> {
+    let mut stats = Vec::new();
+    for chunk in reader.chunks(COLUMN_NAME) {
+        let meshes = try!(try!(chunk.data.get_as::<mesh_capnp::column::Reader>()).get_meshes());
+        for i in 0..meshes.len() {
+            let m = meshes.get(i);
+            let buffers = try!(m.get_buffers());
+            let mut words = Vec::with_capacity(buffers.len() as usize);
+            for j in 0..buffers.len() {
+                words.push(try!(buffers.get(j).total_size()).word_count);
+            }
+            stats.push((m.get_id(), words));
+        }
     }
+    Ok(stats)
 }
@@ -1,4 +1,5 @@
 extern crate capnp;
+#[macro_use]
 extern crate hairball;
 
 #[cfg(feature="build-schema")]
@@ -9,66 +10,124 @@ pub mod index_capnp {
 #[cfg(not(feature="build-schema"))]
 pub mod index_capnp;
 
-const COLUMN_NAME: &'static str = "mesh_index";
+/// The name the column is stored under in a hairball
+pub const COLUMN_NAME: &'static str = "mesh_index";
 
-pub fn write<'a, R>(hb: &mut hairball::Builder, i: &[(u32, &'a R)])
-    where R: AsRef<[u32]>
+/// The schema of the column as a serialized CodeGeneratorRequest, this is
+/// only available if the crate was built with `build-schema`
+#[cfg(feature="build-schema")]
+pub fn schema() -> Option<&'static [u8]> {
+    Some(include_bytes!(concat!(env!("OUT_DIR"), "/index.schema")))
+}
+
+#[cfg(not(feature="build-schema"))]
+pub fn schema() -> Option<&'static [u8]> {
+    None
+}
+
+pub fn write<'a, C, R>(hb: &mut C, i: &[(u32, &'a R)])
+    where C: hairball::Columns,
+          R: AsRef<[u32]>
 {
-    let column: index_capnp::column::Builder = hb.column(COLUMN_NAME)
-        .and_then(|c| c.get_as())
-        .unwrap();
+    let order = hairball::sorted_order(i, |r| r.0);
+    {
+        let column: index_capnp::column::Builder = hb.append_rows(COLUMN_NAME, i.len() as u32)
+            .and_then(|c| c.get_as())
+            .unwrap();
 
-    let mut rows = column.init_meshes(i.len() as u32);
-    for (i, &(name, ref index)) in i.iter().enumerate() {
-        let mut row = rows.borrow().get(i as u32);
-        row.set_id(name);
-        let src = index.as_ref();
-        let mut dst = row.init_index(src.len() as u32);
-        for (i, &s) in src.iter().enumerate() {
-            dst.set(i as u32, s);
+        let mut rows = column.init_meshes(i.len() as u32);
+        for (n, &idx) in order.iter().enumerate() {
+            let (name, index) = i[idx];
+            let mut row = rows.borrow().get(n as u32);
+            row.set_id(name);
+            let src = index.as_ref();
+            let mut dst = row.init_index(src.len() as u32);
+            for (i, &s) in src.iter().enumerate() {
+                dst.set(i as u32, s);
+            }
         }
     }
+    for (row, &idx) in order.iter().enumerate() {
+        hb.reference(COLUMN_NAME, row as u32, i[idx].0);
+    }
+    hb.set_sorted(COLUMN_NAME, true);
+    hb.set_schema(COLUMN_NAME,
+                  <index_capnp::column::Reader as capnp::traits::HasTypeId>::type_id(),
+                  schema());
+}
+
+/// Write out a column of owned indices, this can be used as the write
+/// function of a `hairball::ChunkedWriter`
+pub fn write_owned<C>(hb: &mut C, i: &[(u32, Vec<u32>)])
+    where C: hairball::Columns
+{
+    let rows: Vec<(u32, &Vec<u32>)> = i.iter().map(|&(id, ref idx)| (id, idx)).collect();
+    write(hb, &rows[..]);
 }
 
-impl<'a, E> Iterator for Reader<'a, E> {
-    type Item = (&'a E, Vec<u32>);
+fn decode(m: index_capnp::index::Reader) -> capnp::Result<Vec<u32>> {
+    m.get_index().map(|idx| (0..idx.len()).map(|i| idx.get(i)).collect())
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Ok(meshes) = self.column.get_meshes() {
-            while self.index < meshes.len() {
-                let idx = self.index;
-                self.index += 1;
+column_reader! {
+    index_capnp::column.get_meshes[index],
+    |mapping, row| -> (&'a E, Vec<u32>) => {
+        let id = match try!(mapping.row_entity(row.get_id())) {
+            Some(id) => id,
+            None => return Ok(None)
+        };
+        Ok(Some((id, try!(decode(row)))))
+    },
+    references |row| [row.get_id()]
+}
 
-                let m = meshes.get(idx);
-                let id = match self.reader.entity(m.get_id() as usize) {
-                    Some(id) => id,
-                    None => continue
-                };
+/// Read the column from every layer of an overlay, the rows of an entity
+/// in a later layer replace it's rows in earlier layers
+pub fn read_overlay<'a, E>(overlay: &'a hairball::OverlayMapping<'a, E>)
+    -> Option<Box<Iterator<Item=(&'a E, Vec<u32>)> + 'a>>
+    where E: 'a
+{
+    overlay.column(COLUMN_NAME, |layer| {
+        read(layer).map(|rows| {
+            Box::new(rows.map(|(&id, index)| (id, index)))
+                as Box<Iterator<Item=(usize, Vec<u32>)> + 'a>
+        })
+    }).map(|rows| Box::new(rows) as Box<Iterator<Item=(&'a E, Vec<u32>)> + 'a>)
+}
 
-                if let Ok(idx) = m.get_index() {
-                    let vec: Vec<u32> = (0..idx.len()).map(|i| idx.get(i)).collect();
-                    return Some((id, vec));
+/// Find the index of an entity that was resolved through a `Library`,
+/// sorted chunks are binary searched
+pub fn lookup(link: &hairball::Link) -> Option<Vec<u32>> {
+    for chunk in link.reader.chunks(COLUMN_NAME) {
+        let meshes = chunk.data.get_as::<index_capnp::column::Reader>().ok()
+            .and_then(|c| c.get_meshes().ok());
+        if let Some(meshes) = meshes {
+            let range = if chunk.sorted {
+                hairball::sorted_rows(meshes.len(), link.entity as u32, |i| meshes.get(i).get_id())
+            } else {
+                0..meshes.len()
+            };
+            for i in range {
+                let m = meshes.get(i);
+                if m.get_id() as usize == link.entity {
+                    return decode(m).ok();
                 }
             }
         }
-        None
     }
+    None
 }
 
-pub struct Reader<'a, E:'a> {
-    reader: &'a hairball::ReaderMapping<'a, E>,
-    column: index_capnp::column::Reader<'a>,
-    index: u32
-}
-
-pub fn read<'a, E>(read: &'a hairball::ReaderMapping<'a, E>) -> Option<Reader<'a, E>>
-    where E: 'a
-{
-    read.column(COLUMN_NAME)
-        .and_then(|c| c.get_as().ok() )
-        .map(|c| Reader{
-            reader: read,
-            column: c,
-            index: 0
-        })
+/// The words used by the index list of each row, along with the
+/// entity the row belongs to
+pub fn stats(reader: &hairball::Reader) -> capnp::Result<Vec<(u32, u64)>> {
+    let mut stats = Vec::new();
+    for chunk in reader.chunks(COLUMN_NAME) {
+        let meshes = try!(try!(chunk.data.get_as::<index_capnp::column::Reader>()).get_meshes());
+        for i in 0..meshes.len() {
+            let m = meshes.get(i);
+            stats.push((m.get_id(), try!(m.total_size()).word_count));
+        }
+    }
+    Ok(stats)
 }
@@ -0,0 +1,196 @@
+//! Convert a Wavefront obj and it's material libraries into the columns
+//! of a hairball.
+
+extern crate obj;
+extern crate genmesh;
+extern crate gfx_mesh;
+extern crate hairball;
+extern crate hairball_mesh;
+extern crate hairball_mesh_index;
+extern crate hairball_material;
+extern crate hairball_geometry;
+extern crate hairball_draw_binding;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
+
+use genmesh::{
+    Triangulate,
+    MapToVertices,
+    Vertices,
+    LruIndexer,
+    Indexer
+};
+
+use gfx_mesh::{Attribute, BuildInterlaced};
+
+use hairball::LocalEntity;
+use hairball_material::{Component, Value};
+
+pub const POSITION: &'static str = "a_Position";
+pub const NORMAL: &'static str = "a_Normal";
+pub const TEX0: &'static str = "a_Tex0";
+
+/// The number of groups that are written into each chunk of the mesh columns
+const CHUNK_SIZE: usize = 16;
+
+/// Why an obj could not be converted
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Hairball(hairball::Error)
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<hairball::Error> for Error {
+    fn from(err: hairball::Error) -> Error {
+        Error::Hairball(err)
+    }
+}
+
+fn load_material<P>(p: P,
+                    materials: u32,
+                    builder: &mut hairball::Builder,
+                    names: &mut HashMap<String, u32>,
+                    bindings: &mut Vec<(u32, Component, Value)>) -> Result<(), std::io::Error>
+    where P: AsRef<Path>
+{
+    let file = try!(File::open(p));
+    let mat = obj::Mtl::load(&mut BufReader::new(file));
+    for m in mat.materials {
+        let mid = builder.add_entity(
+            LocalEntity::named(m.name.clone())
+                .parent(materials)
+        );
+        names.insert(m.name.clone(), mid);
+
+        m.ka.map(|v| {
+            bindings.push(
+                (mid, Component::Ambient, Value::Color([v[0], v[1], v[2], 1.]))
+            );
+        });
+        m.kd.map(|v| {
+            bindings.push(
+                (mid, Component::Diffuse, Value::Color([v[0], v[1], v[2], 1.]))
+            );
+        });
+        m.ks.map(|v| {
+            bindings.push(
+                (mid, Component::Specular, Value::Color([v[0], v[1], v[2], 1.]))
+            );
+        });
+    }
+    Ok(())
+}
+
+/// Add the objects, groups and materials of the obj at `path` to `builder`.
+/// Material libraries are loaded from the directory of the obj. Each group
+/// becomes an entity under `geometry` with it's own mesh, the materials are
+/// placed under `material`.
+pub fn convert<P>(path: P, builder: &mut hairball::Builder) -> Result<(), Error>
+    where P: AsRef<Path>
+{
+    let obj_path = path.as_ref();
+    let object = Rc::new({
+        let mut f = BufReader::new(try!(File::open(obj_path)));
+        obj::Obj::load(&mut f)
+    });
+
+    let materials = try!(builder.add_entity_at_path("material"));
+
+    let mut material_names = HashMap::new();
+    let mut material_binding = Vec::new();
+    for m in object.materials().iter() {
+        let mut p = PathBuf::from(obj_path);
+        p.pop();
+        p.push(&m[..]);
+        try!(load_material(p,
+            materials,
+            builder,
+            &mut material_names,
+            &mut material_binding
+        ));
+    }
+    hairball_material::write(builder, &material_binding[..]);
+
+    // a name may contain a '/' so it is not used as a path
+    let geometry = try!(builder.add_entity_at_path("geometry"));
+
+    // The large columns are written in chunks as each group is converted,
+    // so the meshes of the whole object are never held in memory
+    let mut index_column = builder.chunked_writer(
+        hairball_mesh_index::COLUMN_NAME, CHUNK_SIZE,
+        hairball_mesh_index::write_owned::<hairball::ColumnWriter>
+    );
+    let mut mesh_column = builder.chunked_writer(
+        hairball_mesh::COLUMN_NAME, CHUNK_SIZE,
+        hairball_mesh::write_owned::<hairball::ColumnWriter>
+    );
+
+    let mut geometries = Vec::new();
+    let mut draw_binding = Vec::new();
+    for o in object.object_iter() {
+        for g in o.group_iter() {
+            let name = format!("{}.{}.{}", o.name, g.name, g.index);
+
+            let mut vertices = Vec::new();
+            let indices: Vec<u32> = {
+                let object = object.clone();
+                let mut indexer = LruIndexer::new(64, |_, v| {
+                    let (p, t, n): (usize, Option<usize>, Option<usize>) = v;
+                    let p = object.position()[p];
+                    let t = t.map(|t| object.texture()[t]).unwrap_or([0., 0.]);
+                    let n = n.map(|n| object.normal()[n]).unwrap_or([1., 0., 0.]);
+                    vertices.push((p, n, t))
+                });
+
+                g.indices.iter()
+                   .map(|x| *x)
+                   .triangulate()
+                   .vertex(|v| indexer.index(v) as u32)
+                   .vertices()
+                   .collect()
+            };
+
+            let vertices = [Attribute::f32(POSITION, 3), Attribute::f32(NORMAL, 3), Attribute::f32(TEX0, 2)]
+                .build(vertices.into_iter())
+                .unwrap()
+                .owned_attributes();
+
+            let id = builder.add_entity(LocalEntity::named(name).parent(geometry));
+            geometries.push((
+                id,
+                hairball_geometry::Geometry{
+                    mesh: id,
+                    start: 0,
+                    length: indices.len() as u32
+                }
+            ));
+            if let Some(ref m) = g.material {
+                draw_binding.push((
+                    id,
+                    hairball_draw_binding::DrawBinding{
+                        geometry: id,
+                        material: material_names[m]
+                    }
+                ));
+            }
+            index_column.push((id, indices));
+            mesh_column.push((id, vec![vertices]));
+        }
+    }
+
+    builder.commit_chunks(index_column);
+    builder.commit_chunks(mesh_column);
+    hairball_geometry::write(builder, &geometries[..]);
+    hairball_draw_binding::write(builder, &draw_binding[..]);
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,331 @@
+extern crate uuid;
+extern crate hairball;
+extern crate hairball_tools;
+extern crate hairball_mesh;
+extern crate hairball_mesh_index;
+extern crate obj_to_hairball;
+extern crate dae_to_hairball;
+
+use std::env::args;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::exit;
+
+use hairball::Builder;
+
+// Exit codes, a command that ran but found differences or problems
+// exits with `FAILED`, a file that could not be read exits with `USAGE`
+const FAILED: i32 = 1;
+const USAGE: i32 = 2;
+
+fn usage() -> ! {
+    let mut stderr = std::io::stderr();
+    let _ = writeln!(stderr, "usage: hairball info <hairball>");
+    let _ = writeln!(stderr, "       hairball ls <hairball>");
+    let _ = writeln!(stderr, "       hairball columns <hairball>");
+    let _ = writeln!(stderr, "       hairball cat <hairball> <column>");
+    let _ = writeln!(stderr, "       hairball stats <hairball>");
+    let _ = writeln!(stderr, "       hairball verify <hairball>");
+    let _ = writeln!(stderr, "       hairball convert <obj|dae|json> <hairball>");
+    let _ = writeln!(stderr, "       hairball merge <output> <hairball>...");
+    let _ = writeln!(stderr, "       hairball repack <hairball> <output>");
+    let _ = writeln!(stderr, "       hairball diff <old> <new>");
+    let _ = writeln!(stderr, "       hairball dump <hairball> [<column>]");
+    let _ = writeln!(stderr, "       hairball load <json> <hairball>");
+    exit(USAGE);
+}
+
+fn open(path: &str) -> hairball::Reader {
+    match hairball::Reader::read(path) {
+        Ok(r) => r,
+        Err(err) => {
+            let _ = writeln!(std::io::stderr(), "failed to read {}: {:?}", path, err);
+            exit(USAGE);
+        }
+    }
+}
+
+fn create(path: &str, uuid: Option<uuid::Uuid>) -> Builder {
+    let builder = match uuid {
+        Some(uuid) => Builder::new_with_uuid(path, uuid),
+        None => Builder::new(path)
+    };
+    match builder {
+        Ok(b) => b,
+        Err(err) => {
+            let _ = writeln!(std::io::stderr(), "failed to create {}: {:?}", path, err);
+            exit(FAILED);
+        }
+    }
+}
+
+fn close(path: &str, builder: Builder) {
+    if let Err(err) = builder.close() {
+        let _ = writeln!(std::io::stderr(), "failed to write {}: {:?}", path, err);
+        exit(FAILED);
+    }
+}
+
+/// prints the header and segment table of a hairball
+fn info(path: &str) {
+    let hb = open(path);
+    let version = hb.version();
+    let segments = hb.segments();
+    let used = segments.iter().map(|s| s.size).fold(0, |a, b| a + b);
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+
+    println!("uuid: {}", hb.uuid().to_hyphenated_string());
+    println!("version: {}.{}.{}", version[0], version[1], version[2]);
+    println!("revision: {}", hb.revision());
+    println!("entities: {}", hb.entities_len());
+    println!("external files: {}", hb.external_len());
+    println!("columns: {}", hb.column_names().len());
+    println!("file size: {} bytes", size);
+    println!("segment size: {} bytes", used);
+    println!("segments:");
+    for (i, s) in segments.iter().enumerate() {
+        let message = s.message.map(|m| format!("{}", m)).unwrap_or("free".to_owned());
+        println!("  {:4} message {:>4} offset {:10} size {:10}", i, message, s.offset, s.size);
+    }
+}
+
+/// prints every entity under it's parent
+fn ls(path: &str) {
+    let hb = open(path);
+    let len = hb.entities_len();
+
+    let mut roots = Vec::new();
+    let mut children = vec![Vec::new(); len];
+    for i in 0..len {
+        match hb.get_entity(i).and_then(|e| e.parent()) {
+            Some(p) if (p as usize) < len => children[p as usize].push(i),
+            _ => roots.push(i)
+        }
+    }
+
+    // an entity in a parent cycle is never reached from a root
+    let mut stack: Vec<(usize, usize)> = roots.into_iter().rev().map(|i| (i, 0)).collect();
+    while let Some((i, depth)) = stack.pop() {
+        let name = match hb.get_entity(i) {
+            Some(e) => match e.file() {
+                Some(file) => {
+                    format!("{} -> {}", e.name().unwrap_or("<guid>"), file.to_hyphenated_string())
+                }
+                None => e.name().unwrap_or("<anonymous>").to_owned()
+            },
+            None => "<invalid>".to_owned()
+        };
+        println!("{:6} {}{}", i, std::iter::repeat("  ").take(depth).collect::<String>(), name);
+        stack.extend(children[i].iter().rev().map(|&c| (c, depth + 1)));
+    }
+}
+
+/// prints the name, chunks and schema of every column
+fn columns(path: &str) {
+    let hb = open(path);
+    for name in hb.column_names() {
+        let chunks = hb.chunks(name);
+        let schema = match hb.column_schema(name) {
+            Some(id) if hb.schema(id).is_some() => format!("{:#x} (embedded)", id),
+            Some(id) => format!("{:#x}", id),
+            None => "none".to_owned()
+        };
+        println!("{} chunks: {} sorted: {} schema: {}",
+            name, chunks.len(), hb.column_sorted(name), schema);
+    }
+}
+
+/// prints where the bytes of a hairball are used
+fn stats(path: &str) {
+    let hb = open(path);
+    let stats = match hb.stats() {
+        Ok(stats) => stats,
+        Err(err) => {
+            let _ = writeln!(std::io::stderr(), "failed to read {}: {:?}", path, err);
+            exit(FAILED);
+        }
+    };
+
+    let line = |words: u64, what: &str| println!("{:12} bytes  {}", words * 8, what);
+    line(stats.segments, "segments");
+    line(stats.entities, "entities");
+    line(stats.names, "entity names");
+    line(stats.schemas, "schemas");
+    line(stats.tables, "tables");
+    line(stats.padding, "padding");
+    line(stats.unused, "unused");
+    for &(ref name, words) in &stats.columns {
+        line(words, &format!("column {}", name));
+    }
+
+    let entity = |id: u32| hb.path(id as usize).unwrap_or(format!("{}", id));
+    if let Ok(meshes) = hairball_mesh::stats(&hb) {
+        for (id, buffers) in meshes {
+            for (i, words) in buffers.into_iter().enumerate() {
+                line(words, &format!("vertex buffer {} of {}", i, entity(id)));
+            }
+        }
+    }
+    if let Ok(indices) = hairball_mesh_index::stats(&hb) {
+        for (id, words) in indices {
+            line(words, &format!("index of {}", entity(id)));
+        }
+    }
+}
+
+/// prints the problems found in a hairball, exits with 1 if there were any
+fn verify(path: &str) {
+    let problems = hairball_tools::verify(&open(path));
+    for p in &problems {
+        println!("{}", p);
+    }
+    if problems.len() > 0 {
+        exit(FAILED);
+    }
+}
+
+/// writes a hairball from an obj, dae or the JSON written by `dump_json`
+fn convert(src: &str, dst: &str) {
+    let ext = Path::new(src).extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    match ext.as_ref().map(|e| &e[..]) {
+        Some("json") => load(src, dst),
+        Some("obj") => {
+            let mut builder = create(dst, None);
+            if let Err(err) = obj_to_hairball::convert(src, &mut builder) {
+                let _ = writeln!(std::io::stderr(), "failed to convert {}: {:?}", src, err);
+                exit(FAILED);
+            }
+            close(dst, builder);
+        }
+        Some("dae") => {
+            let mut builder = create(dst, None);
+            if let Err(err) = dae_to_hairball::convert(src, &mut builder) {
+                let _ = writeln!(std::io::stderr(), "failed to convert {}: {:?}", src, err);
+                exit(FAILED);
+            }
+            close(dst, builder);
+        }
+        _ => {
+            let _ = writeln!(std::io::stderr(), "don't know how to convert {}", src);
+            exit(USAGE);
+        }
+    }
+}
+
+/// merges every hairball in `src` into a new hairball
+fn merge(dst: &str, src: &[String]) {
+    let src: Vec<hairball::Reader> = src.iter().map(|p| open(p)).collect();
+    let mut builder = create(dst, None);
+    if let Err(err) = hairball_tools::merge(&src, &mut builder) {
+        let _ = writeln!(std::io::stderr(), "failed to merge: {:?}", err);
+        exit(FAILED);
+    }
+    close(dst, builder);
+}
+
+/// rewrites a hairball with the same uuid
+fn repack(src: &str, dst: &str) {
+    let src = open(src);
+    let mut builder = create(dst, Some(src.uuid()));
+    if let Err(err) = hairball_tools::repack(&src, &mut builder) {
+        let _ = writeln!(std::io::stderr(), "failed to repack: {:?}", err);
+        exit(FAILED);
+    }
+    close(dst, builder);
+}
+
+/// prints the differences, exits with 1 if there were any
+fn diff(old: &str, new: &str) {
+    let diff = hairball_tools::diff(&open(old), &open(new));
+    print!("{}", diff);
+    if !diff.is_empty() {
+        exit(FAILED);
+    }
+}
+
+/// prints the hairball as JSON, the columns that are left out are
+/// reported on stderr
+fn dump_json(path: &str) {
+    let hairball = open(path);
+    for column in hairball_tools::skipped_columns(&hairball) {
+        let _ = writeln!(std::io::stderr(), "skipped column {}", column);
+    }
+    println!("{}", hairball_tools::to_json(&hairball).pretty());
+}
+
+/// writes a hairball from the JSON written by `dump_json`
+fn load(src: &str, dst: &str) {
+    let mut text = String::new();
+    if let Err(err) = File::open(src).and_then(|mut f| f.read_to_string(&mut text)) {
+        let _ = writeln!(std::io::stderr(), "failed to read {}: {:?}", src, err);
+        exit(USAGE);
+    }
+    let loaded = hairball_tools::parse(&text)
+        .and_then(|json| hairball_tools::from_json(&json, dst));
+    if let Err(err) = loaded {
+        let _ = writeln!(std::io::stderr(), "failed to load {}: {:?}", src, err);
+        exit(FAILED);
+    }
+}
+
+/// prints a column using the schema embedded in the hairball, a known
+/// column without an embedded schema is printed as JSON one row per line
+fn cat(path: &str, column: &str) {
+    let hb = open(path);
+    let stdout = std::io::stdout();
+    match hairball_tools::dump(&hb, column, &mut stdout.lock()) {
+        Ok(()) => return,
+        Err(hairball_tools::DumpError::NoSchema(_)) => (),
+        Err(err) => {
+            let _ = writeln!(std::io::stderr(), "failed to dump {}: {:?}", column, err);
+            exit(FAILED);
+        }
+    }
+
+    let json = hairball_tools::to_json(&hb);
+    match json.find_path(&["columns", column]).and_then(|c| c.as_array()) {
+        Some(rows) => {
+            for row in rows {
+                println!("{}", row);
+            }
+        }
+        None => {
+            let _ = writeln!(std::io::stderr(), "{} has no schema and is not a known column", column);
+            exit(FAILED);
+        }
+    }
+}
+
+/// prints a column using the schema embedded in the hairball
+fn dump(path: &str, column: &str) {
+    let stdout = std::io::stdout();
+    if let Err(err) = hairball_tools::dump(&open(path), column, &mut stdout.lock()) {
+        let _ = writeln!(std::io::stderr(), "failed to dump {}: {:?}", column, err);
+        exit(FAILED);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = args().skip(1).collect();
+
+    match args.get(0).map(|s| &s[..]) {
+        Some("info") if args.len() == 2 => info(&args[1]),
+        Some("ls") if args.len() == 2 => ls(&args[1]),
+        Some("columns") if args.len() == 2 => columns(&args[1]),
+        Some("cat") if args.len() == 3 => cat(&args[1], &args[2]),
+        Some("stats") if args.len() == 2 => stats(&args[1]),
+        Some("verify") if args.len() == 2 => verify(&args[1]),
+        Some("convert") if args.len() == 3 => convert(&args[1], &args[2]),
+        Some("merge") if args.len() >= 3 => merge(&args[1], &args[2..]),
+        Some("repack") if args.len() == 3 => repack(&args[1], &args[2]),
+        Some("diff") if args.len() == 3 => diff(&args[1], &args[2]),
+        Some("dump") if args.len() == 2 => dump_json(&args[1]),
+        Some("dump") if args.len() == 3 => dump(&args[1], &args[2]),
+        Some("load") if args.len() == 3 => load(&args[1], &args[2]),
+        _ => usage()
+    }
+}
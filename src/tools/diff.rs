@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use hairball::{Reader, Entity};
+
+/// The name used for the entity table in a `Snapshot`
+pub const ENTITY: &'static str = "entity";
+
+/// A description of every entity and every row of the known columns in a
+/// hairball. Rows are keyed by the path of the entity they belong to rather
+/// then it's index, so two hairballs that were written in a different order
+/// can be compared. Rows that belong to an anonymous entity are skipped.
+pub struct Snapshot {
+    columns: Vec<(&'static str, BTreeMap<String, String>)>
+}
+
+/// The key used for an entity, external entities have no parents so
+/// they are prefixed with the file they reference
+fn key(r: &Reader, idx: usize) -> Option<String> {
+    match r.get_entity(idx) {
+        Some(e @ Entity::External(_)) => {
+            let file = e.file().unwrap().to_hyphenated_string();
+            match (e.name(), e.guid()) {
+                (Some(name), _) => Some(format!("<{}>/{}", file, name)),
+                (None, Some(guid)) => Some(format!("<{}>#{}", file, guid.to_hyphenated_string())),
+                (None, None) => None
+            }
+        }
+        Some(Entity::Local(_)) => r.path(idx),
+        None => None
+    }
+}
+
+/// Used to describe an entity that is referenced by a row
+fn reference(keys: &[Option<String>], idx: u32) -> &str {
+    match keys.get(idx as usize) {
+        Some(&Some(ref key)) => &key[..],
+        _ => "<anonymous>"
+    }
+}
+
+impl Snapshot {
+    /// Take a snapshot of a hairball
+    pub fn new(r: &Reader) -> Snapshot {
+        let keys: Vec<Option<String>> = (0..r.entities_len()).map(|i| key(r, i)).collect();
+        let mapping = r.into_mapping(|i| i as u32);
+        let keys = &keys[..];
+
+        let mut entities = BTreeMap::new();
+        for i in 0..r.entities_len() {
+            if let Some(ref k) = keys[i] {
+                let desc = match r.get_entity(i) {
+                    Some(Entity::External(_)) => "external",
+                    _ => "local"
+                };
+                entities.insert(k.clone(), desc.to_owned());
+            }
+        }
+
+        let mut meshes = BTreeMap::new();
+        if let Some(rows) = hairball_mesh::read(&mapping) {
+            for (&id, mesh) in rows {
+                if let Some(ref k) = keys[id as usize] {
+                    let buffers: Vec<String> = mesh.iter().map(|vb| {
+                        let attributes: Vec<String> = vb.attributes().iter()
+                            .map(|a| format!("{} {:?}x{}", a.name, a.element_type, a.element_count))
+                            .collect();
+                        format!("[{}] {} vertices",
+                            attributes.join(", "),
+                            hairball_mesh::vertex_count(vb)
+                        )
+                    }).collect();
+                    meshes.insert(k.clone(), buffers.join(", "));
+                }
+            }
+        }
+
+        let mut indices = BTreeMap::new();
+        if let Some(rows) = hairball_mesh_index::read(&mapping) {
+            for (&id, index) in rows {
+                if let Some(ref k) = keys[id as usize] {
+                    indices.insert(k.clone(), format!("{} indices", index.len()));
+                }
+            }
+        }
+
+        // an entity can have a row for each component, these are sorted
+        // so the order they were written in does not matter
+        let mut components = BTreeMap::new();
+        if let Some(rows) = hairball_material::read(&mapping) {
+            for (&id, component, value) in rows {
+                if let Some(ref k) = keys[id as usize] {
+                    components.entry(k.clone()).or_insert(Vec::new())
+                              .push(format!("{:?} {:?}", component, value));
+                }
+            }
+        }
+        let materials = components.into_iter().map(|(k, mut v): (String, Vec<String>)| {
+            v.sort();
+            (k, v.join(", "))
+        }).collect();
+
+        let mut geometries = BTreeMap::new();
+        if let Some(rows) = hairball_geometry::read(&mapping) {
+            for (&id, g) in rows {
+                if let Some(ref k) = keys[id as usize] {
+                    geometries.insert(k.clone(), format!("mesh {} {}..{}",
+                        reference(keys, *g.mesh),
+                        g.start,
+                        g.start + g.length
+                    ));
+                }
+            }
+        }
+
+        let mut bindings = BTreeMap::new();
+        if let Some(rows) = hairball_draw_binding::read(&mapping) {
+            for (&id, b) in rows {
+                if let Some(ref k) = keys[id as usize] {
+                    bindings.insert(k.clone(), format!("geometry {} material {}",
+                        reference(keys, *b.geometry),
+                        reference(keys, *b.material)
+                    ));
+                }
+            }
+        }
+
+        Snapshot {
+            columns: vec![
+                (ENTITY, entities),
+                (hairball_mesh::COLUMN_NAME, meshes),
+                (hairball_mesh_index::COLUMN_NAME, indices),
+                (hairball_material::COLUMN_NAME, materials),
+                (hairball_geometry::COLUMN_NAME, geometries),
+                (hairball_draw_binding::COLUMN_NAME, bindings)
+            ]
+        }
+    }
+
+    /// Check if an entity and all of it's rows are the same in both snapshots
+    pub fn same(&self, other: &Snapshot, path: &str) -> bool {
+        self.columns.iter().zip(other.columns.iter()).all(|(&(_, ref a), &(_, ref b))| {
+            a.get(path) == b.get(path)
+        })
+    }
+
+    /// Compare this snapshot with a newer one
+    pub fn diff(&self, new: &Snapshot) -> Diff {
+        let mut changes = Vec::new();
+        for (&(column, ref old), &(_, ref new)) in self.columns.iter().zip(new.columns.iter()) {
+            for (path, a) in old.iter() {
+                match new.get(path) {
+                    None => changes.push(Change::Removed(column, path.clone(), a.clone())),
+                    Some(b) if a != b => {
+                        changes.push(Change::Changed(column, path.clone(), a.clone(), b.clone()))
+                    }
+                    Some(_) => ()
+                }
+            }
+            for (path, b) in new.iter() {
+                if !old.contains_key(path) {
+                    changes.push(Change::Added(column, path.clone(), b.clone()));
+                }
+            }
+        }
+        Diff { changes: changes }
+    }
+}
+
+/// A single difference, each variant holds the column (or `ENTITY`),
+/// the path of the row and the description of the row
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added(&'static str, String, String),
+    Removed(&'static str, String, String),
+    Changed(&'static str, String, String, String)
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Added(c, ref p, ref d) => write!(f, "+ {} {}: {}", c, p, d),
+            Change::Removed(c, ref p, ref d) => write!(f, "- {} {}: {}", c, p, d),
+            Change::Changed(c, ref p, ref a, ref b) => write!(f, "~ {} {}: {} -> {}", c, p, a, b)
+        }
+    }
+}
+
+/// The differences between two hairballs, see `diff`
+pub struct Diff {
+    pub changes: Vec<Change>
+}
+
+impl Diff {
+    /// true if the hairballs had no differences
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.changes {
+            try!(writeln!(f, "{}", c));
+        }
+        Ok(())
+    }
+}
+
+/// Compare two hairballs entity by entity and row by row for the known
+/// columns, `old` is the base of the comparison.
+pub fn diff(old: &Reader, new: &Reader) -> Diff {
+    Snapshot::new(old).diff(&Snapshot::new(new))
+}
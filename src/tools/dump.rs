@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ptr;
+
+use capnp;
+use capnp::primitive_list;
+use capnp::private::layout::{StructReader, PointerReader, ListReader, FieldSize};
+use capnp::traits::FromPointerReader;
+use capnpc::schema_capnp::{code_generator_request, node, field, type_};
+use hairball::Reader;
+
+// the discriminant value of a field that is not part of a union
+const NO_DISCRIMINANT: u16 = 0xffff;
+
+/// Why a column could not be dumped
+#[derive(Debug)]
+pub enum DumpError {
+    Io(io::Error),
+    Capnp(capnp::Error),
+    /// An enum in the schema has a value that is not known
+    NotInSchema(u16),
+    /// The hairball does not have the column
+    NoColumn,
+    /// The schema of the column was not embedded in the hairball
+    NoSchema(u64),
+    /// A type used by the column is not in the embedded schema
+    MissingNode(u64)
+}
+
+impl From<io::Error> for DumpError {
+    fn from(err: io::Error) -> DumpError {
+        DumpError::Io(err)
+    }
+}
+
+impl From<capnp::Error> for DumpError {
+    fn from(err: capnp::Error) -> DumpError {
+        DumpError::Capnp(err)
+    }
+}
+
+impl From<capnp::NotInSchema> for DumpError {
+    fn from(err: capnp::NotInSchema) -> DumpError {
+        DumpError::NotInSchema(err.0)
+    }
+}
+
+/// Reads the struct that an `AnyPointer` points to without knowing it's type
+struct Raw<'a>(StructReader<'a>);
+
+impl<'a> FromPointerReader<'a> for Raw<'a> {
+    fn get_from_pointer(reader: &PointerReader<'a>) -> capnp::Result<Raw<'a>> {
+        reader.get_struct(ptr::null()).map(Raw)
+    }
+}
+
+/// Where a value is read from
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    Field(StructReader<'a>, u32),
+    Element(ListReader<'a>, u32)
+}
+
+/// A field of a struct, groups are read from the sections of the struct
+/// that contains them
+#[derive(Clone, Copy)]
+enum Member<'a, 'b> {
+    Value(Source<'b>, type_::Reader<'a>),
+    Group(u64, StructReader<'b>)
+}
+
+type Nodes<'a> = HashMap<u64, node::Reader<'a>>;
+
+/// Print every chunk of `column` using the schema that was embedded in
+/// the hairball when the column was written. Each field of a chunk is
+/// written on it's own line, lists are written one element per line.
+/// Default values in the schema are not applied.
+pub fn dump<W: Write>(reader: &Reader, column: &str, out: &mut W) -> Result<(), DumpError> {
+    let chunks = reader.chunks(column);
+    if chunks.len() == 0 {
+        return Err(DumpError::NoColumn);
+    }
+
+    for chunk in chunks {
+        let id = chunk.schema;
+        let request = match reader.schema(id) {
+            Some(request) => request,
+            None => return Err(DumpError::NoSchema(id))
+        };
+        let message = try!(capnp::serialize::read_message(
+            &mut &request[..],
+            capnp::message::ReaderOptions::new()
+        ));
+        let request = try!(message.get_root::<code_generator_request::Reader>());
+        let list = try!(request.get_nodes());
+        let mut nodes = HashMap::new();
+        for i in 0..list.len() {
+            let n = list.get(i);
+            nodes.insert(n.get_id(), n);
+        }
+
+        let Raw(root) = try!(chunk.data.get_as::<Raw>());
+        for (name, member) in try!(fields(&nodes, id, root)) {
+            try!(write!(out, "{} = ", name));
+            let list = match member {
+                Member::Value(src, ty) if !pointer(src).is_null() => {
+                    match try!(ty.which()) {
+                        type_::Which::List(list) => Some((src, try!(list.get_element_type()))),
+                        _ => None
+                    }
+                }
+                _ => None
+            };
+
+            match list {
+                Some((src, elem)) => {
+                    let list = try!(pointer(src).get_list(field_size(elem), ptr::null()));
+                    try!(writeln!(out, "["));
+                    for i in 0..list.len() {
+                        try!(write!(out, "  "));
+                        try!(write_value(out, &nodes, Source::Element(list, i), elem));
+                        try!(writeln!(out, ""));
+                    }
+                    try!(writeln!(out, "]"));
+                }
+                None => {
+                    try!(write_member(out, &nodes, member));
+                    try!(writeln!(out, ""));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The fields of a struct, only the active field of a union is included
+fn fields<'a, 'b>(nodes: &Nodes<'a>, id: u64, s: StructReader<'b>)
+    -> Result<Vec<(&'a str, Member<'a, 'b>)>, DumpError>
+{
+    let st = match nodes.get(&id).map(|n| n.which()) {
+        Some(Ok(node::Which::Struct(st))) => st,
+        _ => return Err(DumpError::MissingNode(id))
+    };
+    let discriminant = if st.get_discriminant_count() > 0 {
+        Some(s.get_data_field::<u16>(st.get_discriminant_offset()))
+    } else {
+        None
+    };
+
+    let list = try!(st.get_fields());
+    let mut fields = Vec::new();
+    for i in 0..list.len() {
+        let f = list.get(i);
+        let d = f.get_discriminant_value();
+        if d != NO_DISCRIMINANT && Some(d) != discriminant {
+            continue;
+        }
+        let member = match try!(f.which()) {
+            field::Which::Slot(slot) => {
+                Member::Value(Source::Field(s, slot.get_offset()), try!(slot.get_type()))
+            }
+            field::Which::Group(group) => Member::Group(group.get_type_id(), s)
+        };
+        fields.push((try!(f.get_name()), member));
+    }
+    Ok(fields)
+}
+
+fn write_member<W: Write>(out: &mut W, nodes: &Nodes, member: Member) -> Result<(), DumpError> {
+    match member {
+        Member::Value(src, ty) => write_value(out, nodes, src, ty),
+        Member::Group(id, s) => write_struct(out, nodes, id, s)
+    }
+}
+
+fn pointer<'a>(src: Source<'a>) -> PointerReader<'a> {
+    match src {
+        Source::Field(s, offset) => s.get_pointer_field(offset),
+        Source::Element(l, i) => l.get_pointer_element(i)
+    }
+}
+
+/// The size of each element of a list of `ty`
+fn field_size(ty: type_::Reader) -> FieldSize {
+    use capnpc::schema_capnp::type_::Which::*;
+
+    match ty.which() {
+        Ok(Void(_)) => FieldSize::Void,
+        Ok(Bool(_)) => FieldSize::Bit,
+        Ok(Int8(_)) | Ok(Uint8(_)) => FieldSize::Byte,
+        Ok(Int16(_)) | Ok(Uint16(_)) | Ok(Enum(_)) => FieldSize::TwoBytes,
+        Ok(Int32(_)) | Ok(Uint32(_)) | Ok(Float32(_)) => FieldSize::FourBytes,
+        Ok(Int64(_)) | Ok(Uint64(_)) | Ok(Float64(_)) => FieldSize::EightBytes,
+        Ok(Struct(_)) => FieldSize::InlineComposite,
+        _ => FieldSize::Pointer
+    }
+}
+
+fn write_value<W: Write>(out: &mut W, nodes: &Nodes, src: Source, ty: type_::Reader)
+    -> Result<(), DumpError>
+{
+    use capnpc::schema_capnp::type_::Which::*;
+
+    macro_rules! primitive {
+        ($t:ty) => {
+            match src {
+                Source::Field(s, offset) => s.get_data_field::<$t>(offset),
+                Source::Element(l, i) => primitive_list::Reader::<$t>::new(l).get(i)
+            }
+        }
+    }
+
+    match try!(ty.which()) {
+        Void(_) => try!(write!(out, "void")),
+        Bool(_) => {
+            let value = match src {
+                Source::Field(s, offset) => s.get_bool_field(offset),
+                Source::Element(l, i) => primitive_list::Reader::<bool>::new(l).get(i)
+            };
+            try!(write!(out, "{}", value));
+        }
+        Int8(_) => try!(write!(out, "{}", primitive!(i8))),
+        Int16(_) => try!(write!(out, "{}", primitive!(i16))),
+        Int32(_) => try!(write!(out, "{}", primitive!(i32))),
+        Int64(_) => try!(write!(out, "{}", primitive!(i64))),
+        Uint8(_) => try!(write!(out, "{}", primitive!(u8))),
+        Uint16(_) => try!(write!(out, "{}", primitive!(u16))),
+        Uint32(_) => try!(write!(out, "{}", primitive!(u32))),
+        Uint64(_) => try!(write!(out, "{}", primitive!(u64))),
+        Float32(_) => try!(write!(out, "{}", primitive!(f32))),
+        Float64(_) => try!(write!(out, "{}", primitive!(f64))),
+        Enum(e) => {
+            let value = primitive!(u16);
+            let name = match nodes.get(&e.get_type_id()).map(|n| n.which()) {
+                Some(Ok(node::Which::Enum(e))) => {
+                    let enumerants = try!(e.get_enumerants());
+                    if value < enumerants.len() as u16 {
+                        Some(try!(enumerants.get(value as u32).get_name()))
+                    } else {
+                        None
+                    }
+                }
+                _ => None
+            };
+            match name {
+                Some(name) => try!(write!(out, "{}", name)),
+                None => try!(write!(out, "{}", value))
+            }
+        }
+        Struct(st) => {
+            let s = match src {
+                Source::Field(s, offset) => {
+                    let p = s.get_pointer_field(offset);
+                    if p.is_null() {
+                        return Ok(try!(write!(out, "null")));
+                    }
+                    try!(p.get_struct(ptr::null()))
+                }
+                Source::Element(l, i) => l.get_struct_element(i)
+            };
+            try!(write_struct(out, nodes, st.get_type_id(), s));
+        }
+        _ if pointer(src).is_null() => try!(write!(out, "null")),
+        Text(_) => try!(write!(out, "{:?}", try!(pointer(src).get_text(ptr::null(), 0)))),
+        // vertex data can be large, only it's size is written
+        Data(_) => try!(write!(out, "<{} bytes>", try!(pointer(src).get_data(ptr::null(), 0)).len())),
+        List(list) => {
+            let elem = try!(list.get_element_type());
+            let list = try!(pointer(src).get_list(field_size(elem), ptr::null()));
+            try!(write!(out, "["));
+            for i in 0..list.len() {
+                if i > 0 {
+                    try!(write!(out, ", "));
+                }
+                try!(write_value(out, nodes, Source::Element(list, i), elem));
+            }
+            try!(write!(out, "]"));
+        }
+        Interface(_) => try!(write!(out, "<capability>")),
+        AnyPointer(_) => try!(write!(out, "<any pointer>"))
+    }
+    Ok(())
+}
+
+fn write_struct<W: Write>(out: &mut W, nodes: &Nodes, id: u64, s: StructReader)
+    -> Result<(), DumpError>
+{
+    try!(write!(out, "("));
+    for (i, (name, member)) in try!(fields(nodes, id, s)).into_iter().enumerate() {
+        if i > 0 {
+            try!(write!(out, ", "));
+        }
+        try!(write!(out, "{} = ", name));
+        try!(write_member(out, nodes, member));
+    }
+    try!(write!(out, ")"));
+    Ok(())
+}
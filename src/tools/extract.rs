@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use hairball::{Builder, Reader, Entity, LocalEntity};
+
+use rows::Rows;
+
+/// Copy `entity`, it's descendants and every entity they reference through
+/// the known columns into `dst`. A draw binding pulls in it's geometry and
+/// material, a geometry pulls in it's mesh. The parents of every copied
+/// entity are also copied so their paths are the same as in `src`, but
+/// the column rows of a parent are only copied if it was referenced.
+///
+/// Returns the id of `entity` in `dst`, or None if `entity` does not exist.
+pub fn extract(src: &Reader, entity: u32, dst: &mut Builder) -> Option<u32> {
+    let len = src.entities_len();
+    if entity as usize >= len {
+        return None;
+    }
+
+    let mut children = vec![Vec::new(); len];
+    for i in 0..len {
+        if let Some(p) = src.get_entity(i).and_then(|e| e.parent()) {
+            if (p as usize) < len {
+                children[p as usize].push(i as u32);
+            }
+        }
+    }
+
+    let mapping = src.into_mapping(|i| i as u32);
+    let rows = Rows::read(&mapping);
+
+    let mut references: HashMap<u32, Vec<u32>> = HashMap::new();
+    for &(id, g) in rows.geometries.iter().flat_map(|r| r.iter()) {
+        references.entry(id).or_insert(Vec::new()).push(g.mesh);
+    }
+    for &(id, b) in rows.bindings.iter().flat_map(|r| r.iter()) {
+        let r = references.entry(id).or_insert(Vec::new());
+        r.push(b.geometry);
+        r.push(b.material);
+    }
+
+    // walk the descendants of `entity` and everything they reference,
+    // the descendants of a referenced entity are not copied
+    let mut needed = vec![false; len];
+    let mut stack = vec![(entity, true)];
+    while let Some((e, descend)) = stack.pop() {
+        if (e as usize) >= len || needed[e as usize] {
+            continue;
+        }
+        needed[e as usize] = true;
+
+        if descend {
+            stack.extend(children[e as usize].iter().map(|&c| (c, true)));
+        }
+        if let Some(r) = references.get(&e) {
+            stack.extend(r.iter().map(|&r| (r, false)));
+        }
+    }
+
+    let mut keep = needed.clone();
+    for i in 0..len {
+        if !needed[i] {
+            continue;
+        }
+        let mut parent = src.get_entity(i).and_then(|e| e.parent());
+        while let Some(p) = parent {
+            if (p as usize) >= len || keep[p as usize] {
+                break;
+            }
+            keep[p as usize] = true;
+            parent = src.get_entity(p as usize).and_then(|e| e.parent());
+        }
+    }
+
+    let mut next = dst.entities_len() as u32;
+    let ids: Vec<Option<u32>> = keep.iter().map(|&k| {
+        if k {
+            next += 1;
+            Some(next - 1)
+        } else {
+            None
+        }
+    }).collect();
+
+    for i in 0..len {
+        if !keep[i] {
+            continue;
+        }
+
+        match src.get_entity(i).unwrap() {
+            Entity::Local(e) => {
+                dst.add_entity(LocalEntity {
+                    parent: e.parent.and_then(|p| ids.get(p as usize).and_then(|&p| p)),
+                    .. e.into_owned()
+                });
+            }
+            Entity::External(e) => {
+                dst.add_external_entity(e.into_owned());
+            }
+        }
+    }
+
+    // a row is copied if the entity it belongs to was needed, anything
+    // it references was needed as well
+    rows.write(dst, |e| needed[e as usize], |e| ids[e as usize].unwrap());
+
+    ids[entity as usize]
+}
@@ -0,0 +1,37 @@
+//! Tools that operate on whole hairballs. These understand the common
+//! columns (`mesh`, `mesh_index`, `material`, `geometry` and `draw_binding`)
+//! and keep the entity ids stored inside of them valid. Any other column
+//! can be printed with `dump` if it's schema was embedded. A hairball can
+//! be converted to and from JSON with `to_json` and `from_json`, checked
+//! with `verify` and rewritten compactly with `repack`.
+
+extern crate uuid;
+extern crate capnp;
+extern crate capnpc;
+extern crate rustc_serialize;
+extern crate gfx_mesh;
+extern crate hairball;
+extern crate hairball_mesh;
+extern crate hairball_mesh_index;
+extern crate hairball_material;
+extern crate hairball_geometry;
+extern crate hairball_draw_binding;
+
+pub use merge::{merge, MergeError};
+pub use extract::extract;
+pub use diff::{diff, Diff, Change, Snapshot};
+pub use rebase::rebase;
+pub use dump::{dump, DumpError};
+pub use text::{to_json, from_json, parse, skipped_columns, TextError};
+pub use verify::{verify, Problem};
+pub use repack::repack;
+
+mod rows;
+mod merge;
+mod extract;
+mod diff;
+mod rebase;
+mod dump;
+mod text;
+mod verify;
+mod repack;
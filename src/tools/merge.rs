@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use hairball::{Builder, Reader, Entity, LocalEntity};
+
+use rows::Rows;
+
+/// Where an entity from one of the source hairballs ends up
+#[derive(Copy, Clone)]
+enum Slot {
+    /// The entity is written to the merged hairball with this id
+    Entity(u32),
+    /// The entity was an external reference into another merged hairball,
+    /// it is replaced by the local entity it points at (file, entity)
+    Alias(usize, usize)
+}
+
+/// The columns that `merge` knows how to remap, these are also the only
+/// columns that `to_json` writes
+pub const KNOWN_COLUMNS: [&'static str; 5] = [
+    hairball_mesh::COLUMN_NAME,
+    hairball_mesh_index::COLUMN_NAME,
+    hairball_material::COLUMN_NAME,
+    hairball_geometry::COLUMN_NAME,
+    hairball_draw_binding::COLUMN_NAME
+];
+
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+    /// The source hairballs have columns that are not known, so the
+    /// entity ids inside of them cannot be remapped
+    UnknownColumns(Vec<String>)
+}
+
+/// Find the file and entity that an external entity points at if the
+/// file it references is one of the hairballs being merged.
+fn resolve(e: &Entity<&str>,
+           files: &HashMap<Uuid, usize>,
+           paths: &[HashMap<String, usize>],
+           guids: &[HashMap<Uuid, usize>]) -> Option<(usize, usize)> {
+    let file = match e.file().and_then(|f| files.get(f)) {
+        Some(&file) => file,
+        None => return None
+    };
+    e.name()
+     .and_then(|name| paths[file].get(name))
+     .or_else(|| e.guid().and_then(|guid| guids[file].get(guid)))
+     .map(|&idx| (file, idx))
+}
+
+/// Merge the hairballs in `src` into `dst`. The entities of each hairball
+/// are placed under a new root entity that is named after the hairball's
+/// uuid, the ids of the root entities are returned in the same order as `src`.
+///
+/// External entities that reference another hairball in `src` are replaced
+/// by the local entity they point at, any that cannot be resolved are kept
+/// as external entities. The known columns are rewritten with the remapped
+/// entity ids, any column of the same name in `dst` is replaced. Other
+/// columns cannot be remapped, if any of `src` has one nothing is added to
+/// `dst` and the names of the columns are returned as an error.
+pub fn merge(src: &[Reader], dst: &mut Builder) -> Result<Vec<u32>, MergeError> {
+    let mut unknown = Vec::new();
+    for r in src {
+        for name in r.column_names() {
+            let known = KNOWN_COLUMNS.iter().any(|&k| k == name);
+            if !known && !unknown.iter().any(|u: &String| u == name) {
+                unknown.push(name.to_owned());
+            }
+        }
+    }
+    if unknown.len() > 0 {
+        return Err(MergeError::UnknownColumns(unknown));
+    }
+
+    let mut files = HashMap::new();
+    for (i, r) in src.iter().enumerate() {
+        files.entry(r.uuid()).or_insert(i);
+    }
+
+    let paths: Vec<HashMap<String, usize>> = src.iter().map(|r| {
+        (0..r.entities_len())
+            .filter_map(|i| r.path(i).map(|p| (p, i)))
+            .collect()
+    }).collect();
+
+    let guids: Vec<HashMap<Uuid, usize>> = src.iter().map(|r| {
+        (0..r.entities_len())
+            .filter_map(|i| {
+                match r.get_entity(i) {
+                    Some(ref e @ Entity::Local(_)) => e.guid().map(|&g| (g, i)),
+                    _ => None
+                }
+            })
+            .collect()
+    }).collect();
+
+    // assign ids in the same order as the entities are added below
+    let mut next = dst.entities_len() as u32;
+    let mut roots = Vec::new();
+    let mut slots = Vec::new();
+    for r in src {
+        roots.push(next);
+        next += 1;
+
+        slots.push((0..r.entities_len()).map(|i| {
+            match resolve(&r.get_entity(i).unwrap(), &files, &paths[..], &guids[..]) {
+                Some((file, idx)) => Slot::Alias(file, idx),
+                None => {
+                    next += 1;
+                    Slot::Entity(next - 1)
+                }
+            }
+        }).collect::<Vec<Slot>>());
+    }
+
+    // aliases always point at a local entity since only local
+    // entities have a path
+    let ids: Vec<Vec<u32>> = slots.iter().map(|s| {
+        s.iter().map(|&slot| {
+            match slot {
+                Slot::Entity(id) => id,
+                Slot::Alias(file, idx) => match slots[file][idx] {
+                    Slot::Entity(id) => id,
+                    Slot::Alias(_, _) => unreachable!()
+                }
+            }
+        }).collect()
+    }).collect();
+
+    for (i, r) in src.iter().enumerate() {
+        dst.add_entity(LocalEntity::named(r.uuid().to_hyphenated_string()));
+
+        for k in 0..r.entities_len() {
+            if let Slot::Alias(_, _) = slots[i][k] {
+                continue;
+            }
+
+            match r.get_entity(k).unwrap() {
+                Entity::Local(e) => {
+                    let parent = e.parent
+                        .and_then(|p| ids[i].get(p as usize).map(|&p| p))
+                        .unwrap_or(roots[i]);
+                    dst.add_entity(LocalEntity {
+                        parent: Some(parent),
+                        .. e.into_owned()
+                    });
+                }
+                Entity::External(e) => {
+                    dst.add_external_entity(e.into_owned());
+                }
+            }
+        }
+    }
+
+    let mappings: Vec<_> = src.iter().enumerate()
+        .map(|(i, r)| r.into_mapping(|k| ids[i][k]))
+        .collect();
+
+    let mut rows = Rows::empty();
+    for m in &mappings {
+        rows.append(Rows::read(m));
+    }
+    rows.write(dst, |_| true, |id| id);
+
+    Ok(roots)
+}
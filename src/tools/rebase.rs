@@ -0,0 +1,70 @@
+use hairball::{Builder, Reader, Entity, LocalEntity, ExternalEntity};
+
+use diff::Snapshot;
+use rows::Rows;
+
+/// Write `derived` into `dst` with every local entity that is unchanged
+/// in `base` replaced by an external reference into `base`. An entity is
+/// unchanged if it has the same path and the same rows in the known columns,
+/// rows are compared using the paths of the entities they reference.
+///
+/// A local entity cannot have an external parent, so an entity is only
+/// replaced if all of it's children are. Entity ids are kept, offset by
+/// the number of entities already in `dst`. Only the known columns are
+/// written to `dst`, any other column of `derived` is left out. Returns the
+/// number of entities that were replaced.
+pub fn rebase(derived: &Reader, base: &Reader, dst: &mut Builder) -> usize {
+    let len = derived.entities_len();
+    let old = Snapshot::new(base);
+    let new = Snapshot::new(derived);
+    let paths: Vec<Option<String>> = (0..len).map(|i| derived.path(i)).collect();
+
+    let mut children = vec![Vec::new(); len];
+    for i in 0..len {
+        if let Some(p) = derived.get_entity(i).and_then(|e| e.parent()) {
+            if (p as usize) < len {
+                children[p as usize].push(i);
+            }
+        }
+    }
+
+    // visit the deepest entities first so every child is checked before
+    // it's parent, entities without a path are never replaced
+    let mut order: Vec<usize> = (0..len).filter(|&i| paths[i].is_some()).collect();
+    let depth: Vec<usize> = paths.iter()
+        .map(|p| p.as_ref().map(|p| p.split('/').count()).unwrap_or(0))
+        .collect();
+    order.sort_by(|&a, &b| depth[b].cmp(&depth[a]));
+
+    let mut replace = vec![false; len];
+    for i in order {
+        replace[i] = children[i].iter().all(|&c| replace[c]) &&
+                     new.same(&old, paths[i].as_ref().unwrap());
+    }
+
+    let offset = dst.entities_len() as u32;
+    for i in 0..len {
+        if replace[i] {
+            dst.add_external_entity(ExternalEntity::new(base.uuid(), paths[i].clone().unwrap()));
+            continue;
+        }
+
+        match derived.get_entity(i).unwrap() {
+            Entity::Local(e) => {
+                dst.add_entity(LocalEntity {
+                    parent: e.parent.map(|p| p + offset),
+                    .. e.into_owned()
+                });
+            }
+            Entity::External(e) => {
+                dst.add_external_entity(e.into_owned());
+            }
+        }
+    }
+
+    // the rows of a replaced entity are already in `base`
+    let mapping = derived.into_mapping(|i| i as u32);
+    Rows::read(&mapping).write(dst, |e| !replace[e as usize], |e| e + offset);
+
+    replace.iter().filter(|&&r| r).count()
+}
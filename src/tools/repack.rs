@@ -0,0 +1,38 @@
+use hairball::{Builder, Reader, Entity, Error};
+
+use rows::Rows;
+use merge::KNOWN_COLUMNS;
+
+/// Copy `src` into `dst` with the rows of each known column rewritten
+/// into a single sorted chunk, rows that cannot be read are dropped.
+/// Any other column is copied as is, including it's chunks, and so are
+/// the tombstones of every column. Entities,
+/// pins and the revision are copied so `dst` should be a new hairball,
+/// entity ids are the same as in `src`.
+pub fn repack(src: &Reader, dst: &mut Builder) -> Result<(), Error> {
+    for i in 0..src.entities_len() {
+        match src.get_entity(i).unwrap() {
+            Entity::Local(e) => { dst.add_entity(e.into_owned()); }
+            Entity::External(e) => { dst.add_external_entity(e.into_owned()); }
+        }
+    }
+    for i in 0..src.external_len() {
+        if let (Some(file), Some(pin)) = (src.external(i), src.pin(i)) {
+            dst.add_pin(file, pin);
+        }
+    }
+    dst.set_revision(src.revision());
+
+    for name in src.column_names() {
+        if !KNOWN_COLUMNS.iter().any(|&k| k == name) {
+            try!(dst.copy_column(src, name));
+        }
+    }
+    for name in src.tombstoned_columns() {
+        try!(dst.delete_rows(name, &src.tombstones(name)[..]));
+    }
+
+    let mapping = src.into_mapping(|i| i as u32);
+    Rows::read(&mapping).write(dst, |_| true, |e| e);
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use hairball::{Builder, ReaderMapping};
+use hairball_mesh::Mesh;
+use hairball_material::{Component, Value};
+use hairball_geometry::Geometry;
+use hairball_draw_binding::DrawBinding;
+
+/// The rows of every known column, a column is None if the hairball
+/// it was read from did not have it. Columns that are not known are not
+/// read, the entity ids inside of them cannot be remapped, so anything
+/// that is written from `Rows` leaves them out.
+pub struct Rows<'a> {
+    pub meshes: Option<Vec<(u32, Mesh<'a>)>>,
+    pub indices: Option<Vec<(u32, Vec<u32>)>>,
+    pub materials: Option<Vec<(u32, Component, Value)>>,
+    pub geometries: Option<Vec<(u32, Geometry<u32>)>>,
+    pub bindings: Option<Vec<(u32, DrawBinding<u32>)>>
+}
+
+fn append<T>(dst: &mut Option<Vec<T>>, src: Option<Vec<T>>) {
+    match (dst.take(), src) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b.into_iter());
+            *dst = Some(a);
+        }
+        (a, b) => *dst = a.or(b)
+    }
+}
+
+impl<'a> Rows<'a> {
+    /// No columns
+    pub fn empty() -> Rows<'a> {
+        Rows {
+            meshes: None,
+            indices: None,
+            materials: None,
+            geometries: None,
+            bindings: None
+        }
+    }
+
+    /// Read every known column, the ids are those of the mapping
+    pub fn read(m: &'a ReaderMapping<'a, u32>) -> Rows<'a> {
+        Rows {
+            meshes: hairball_mesh::read(m)
+                .map(|rows| rows.map(|(&id, mesh)| (id, mesh)).collect()),
+            indices: hairball_mesh_index::read(m)
+                .map(|rows| rows.map(|(&id, index)| (id, index)).collect()),
+            materials: hairball_material::read(m)
+                .map(|rows| rows.map(|(&id, c, v)| (id, c, v)).collect()),
+            geometries: hairball_geometry::read(m)
+                .map(|rows| rows.map(|(&id, g)| {
+                    (id, Geometry {
+                        mesh: *g.mesh,
+                        start: g.start,
+                        length: g.length
+                    })
+                }).collect()),
+            bindings: hairball_draw_binding::read(m)
+                .map(|rows| rows.map(|(&id, b)| {
+                    (id, DrawBinding {
+                        geometry: *b.geometry,
+                        material: *b.material
+                    })
+                }).collect())
+        }
+    }
+
+    /// Add the rows of `other` to the end of these rows
+    pub fn append(&mut self, other: Rows<'a>) {
+        append(&mut self.meshes, other.meshes);
+        append(&mut self.indices, other.indices);
+        append(&mut self.materials, other.materials);
+        append(&mut self.geometries, other.geometries);
+        append(&mut self.bindings, other.bindings);
+    }
+
+    /// Write the rows that belong to an entity that `keep` returns true for,
+    /// every id is passed through `id` as it's written. Any column of the
+    /// same name in `dst` is replaced.
+    pub fn write<K, I>(&self, dst: &mut Builder, keep: K, id: I)
+        where K: Fn(u32) -> bool,
+              I: Fn(u32) -> u32
+    {
+        if let Some(ref rows) = self.meshes {
+            let x: Vec<(u32, &Mesh)> = rows.iter()
+                .filter(|&&(e, _)| keep(e))
+                .map(|&(e, ref mesh)| (id(e), mesh))
+                .collect();
+            dst.remove_column(hairball_mesh::COLUMN_NAME);
+            hairball_mesh::write(dst, &x[..]);
+        }
+
+        if let Some(ref rows) = self.indices {
+            let x: Vec<(u32, &Vec<u32>)> = rows.iter()
+                .filter(|&&(e, _)| keep(e))
+                .map(|&(e, ref index)| (id(e), index))
+                .collect();
+            dst.remove_column(hairball_mesh_index::COLUMN_NAME);
+            hairball_mesh_index::write(dst, &x[..]);
+        }
+
+        // texture values are not entity ids and are copied as is
+        if let Some(ref rows) = self.materials {
+            let x: Vec<_> = rows.iter()
+                .filter(|&&(e, _, _)| keep(e))
+                .map(|&(e, c, v)| (id(e), c, v))
+                .collect();
+            dst.remove_column(hairball_material::COLUMN_NAME);
+            hairball_material::write(dst, &x[..]);
+        }
+
+        if let Some(ref rows) = self.geometries {
+            let x: Vec<_> = rows.iter()
+                .filter(|&&(e, _)| keep(e))
+                .map(|&(e, g)| {
+                    (id(e), Geometry {
+                        mesh: id(g.mesh),
+                        start: g.start,
+                        length: g.length
+                    })
+                })
+                .collect();
+            dst.remove_column(hairball_geometry::COLUMN_NAME);
+            hairball_geometry::write(dst, &x[..]);
+        }
+
+        if let Some(ref rows) = self.bindings {
+            let x: Vec<_> = rows.iter()
+                .filter(|&&(e, _)| keep(e))
+                .map(|&(e, b)| {
+                    (id(e), DrawBinding {
+                        geometry: id(b.geometry),
+                        material: id(b.material)
+                    })
+                })
+                .collect();
+            dst.remove_column(hairball_draw_binding::COLUMN_NAME);
+            hairball_draw_binding::write(dst, &x[..]);
+        }
+    }
+}
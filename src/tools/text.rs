@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+use rustc_serialize::json::{self, Json};
+use rustc_serialize::hex::{ToHex, FromHex};
+use uuid::Uuid;
+use gfx_mesh::Interlaced;
+
+use hairball::{self, Builder, Reader, Entity, LocalEntity, ExternalEntity, Pin};
+use hairball_mesh::OwnedMesh;
+use hairball_material::{Component, Value};
+use hairball_geometry::Geometry;
+use hairball_draw_binding::DrawBinding;
+
+use rows::Rows;
+use merge::KNOWN_COLUMNS;
+
+/// Why a hairball could not be loaded from it's text representation
+#[derive(Debug)]
+pub enum TextError {
+    Json(json::ParserError),
+    Hairball(hairball::Error),
+    /// The JSON is not a hairball, the string describes what was wrong
+    Invalid(String)
+}
+
+impl From<json::ParserError> for TextError {
+    fn from(err: json::ParserError) -> TextError {
+        TextError::Json(err)
+    }
+}
+
+impl From<hairball::Error> for TextError {
+    fn from(err: hairball::Error) -> TextError {
+        TextError::Hairball(err)
+    }
+}
+
+fn invalid<T>(what: &str) -> Result<T, TextError> {
+    Err(TextError::Invalid(what.to_owned()))
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+}
+
+fn uuid_json(uuid: &Uuid) -> Json {
+    Json::String(uuid.to_hyphenated_string())
+}
+
+/// Describe a hairball as JSON. This includes the uuid, every entity with
+/// it's path, the pins of external files, the rows of the known columns
+/// (`mesh`, `mesh_index`, `material`, `geometry` and `draw_binding`) and
+/// the tombstones of any column. Other columns are not included, see
+/// `skipped_columns`. Vertex data is written as hex.
+pub fn to_json(r: &Reader) -> Json {
+    let mut root = BTreeMap::new();
+    root.insert("uuid".to_owned(), uuid_json(&r.uuid()));
+    root.insert("revision".to_owned(), Json::U64(r.revision()));
+
+    let entities = (0..r.entities_len()).map(|i| {
+        let mut e = BTreeMap::new();
+        match r.get_entity(i) {
+            Some(Entity::Local(l)) => {
+                if let Some(name) = l.name {
+                    e.insert("name".to_owned(), Json::String(name.to_owned()));
+                }
+                if let Some(parent) = l.parent {
+                    e.insert("parent".to_owned(), Json::U64(parent as u64));
+                }
+                if let Some(path) = r.path(i) {
+                    e.insert("path".to_owned(), Json::String(path));
+                }
+            }
+            Some(ref x @ Entity::External(_)) => {
+                e.insert("file".to_owned(), uuid_json(x.file().unwrap()));
+                if let Some(path) = x.name() {
+                    e.insert("path".to_owned(), Json::String(path.to_owned()));
+                }
+            }
+            None => ()
+        }
+        if let Some(guid) = r.get_entity(i).as_ref().and_then(|e| e.guid()) {
+            e.insert("guid".to_owned(), uuid_json(guid));
+        }
+        Json::Object(e)
+    }).collect();
+    root.insert("entities".to_owned(), Json::Array(entities));
+
+    let mut pins = BTreeMap::new();
+    for i in 0..r.external_len() {
+        if let (Some(file), Some(pin)) = (r.external(i), r.pin(i)) {
+            pins.insert(file.to_hyphenated_string(), object(vec![
+                ("hash", Json::U64(pin.hash)),
+                ("revision", Json::U64(pin.revision))
+            ]));
+        }
+    }
+    if pins.len() > 0 {
+        root.insert("pins".to_owned(), Json::Object(pins));
+    }
+
+    let mut tombstones = BTreeMap::new();
+    for column in r.tombstoned_columns() {
+        let entities = r.tombstones(column).into_iter().map(|e| Json::U64(e as u64)).collect();
+        tombstones.insert(column.to_owned(), Json::Array(entities));
+    }
+    if tombstones.len() > 0 {
+        root.insert("tombstones".to_owned(), Json::Object(tombstones));
+    }
+
+    let mapping = r.into_mapping(|i| i as u32);
+    root.insert("columns".to_owned(), columns_json(&Rows::read(&mapping)));
+    Json::Object(root)
+}
+
+/// The columns of a hairball that are left out by `to_json`
+pub fn skipped_columns(r: &Reader) -> Vec<&str> {
+    r.column_names().into_iter()
+        .filter(|&name| !KNOWN_COLUMNS.iter().any(|&k| k == name))
+        .collect()
+}
+
+fn columns_json(rows: &Rows) -> Json {
+    let mut columns = BTreeMap::new();
+
+    if let Some(ref meshes) = rows.meshes {
+        columns.insert(hairball_mesh::COLUMN_NAME.to_owned(), Json::Array(meshes.iter().map(|&(id, ref mesh)| {
+            let buffers = mesh.iter().map(|vb| {
+                let attributes = vb.attributes().iter().map(|a| {
+                    object(vec![
+                        ("name", Json::String(a.name.to_owned())),
+                        ("type", Json::String(hairball_mesh::type_name(a).to_owned())),
+                        ("count", Json::U64(a.element_count as u64))
+                    ])
+                }).collect();
+                object(vec![
+                    ("attributes", Json::Array(attributes)),
+                    ("data", Json::String(vb.data().to_hex()))
+                ])
+            }).collect();
+            object(vec![
+                ("entity", Json::U64(id as u64)),
+                ("buffers", Json::Array(buffers))
+            ])
+        }).collect()));
+    }
+
+    if let Some(ref indices) = rows.indices {
+        columns.insert(hairball_mesh_index::COLUMN_NAME.to_owned(), Json::Array(indices.iter().map(|&(id, ref index)| {
+            object(vec![
+                ("entity", Json::U64(id as u64)),
+                ("index", Json::Array(index.iter().map(|&i| Json::U64(i as u64)).collect()))
+            ])
+        }).collect()));
+    }
+
+    if let Some(ref materials) = rows.materials {
+        columns.insert(hairball_material::COLUMN_NAME.to_owned(), Json::Array(materials.iter().map(|&(id, c, v)| {
+            let component = match c {
+                Component::Ambient => "ambient",
+                Component::Diffuse => "diffuse",
+                Component::Specular => "specular"
+            };
+            let value = match v {
+                Value::Texture(t) => ("texture", Json::U64(t as u64)),
+                Value::Color(c) => ("color", Json::Array(c.iter().map(|&x| Json::F64(x as f64)).collect()))
+            };
+            object(vec![
+                ("entity", Json::U64(id as u64)),
+                ("component", Json::String(component.to_owned())),
+                value
+            ])
+        }).collect()));
+    }
+
+    if let Some(ref geometries) = rows.geometries {
+        columns.insert(hairball_geometry::COLUMN_NAME.to_owned(), Json::Array(geometries.iter().map(|&(id, g)| {
+            object(vec![
+                ("entity", Json::U64(id as u64)),
+                ("mesh", Json::U64(g.mesh as u64)),
+                ("start", Json::U64(g.start as u64)),
+                ("length", Json::U64(g.length as u64))
+            ])
+        }).collect()));
+    }
+
+    if let Some(ref bindings) = rows.bindings {
+        columns.insert(hairball_draw_binding::COLUMN_NAME.to_owned(), Json::Array(bindings.iter().map(|&(id, b)| {
+            object(vec![
+                ("entity", Json::U64(id as u64)),
+                ("geometry", Json::U64(b.geometry as u64)),
+                ("material", Json::U64(b.material as u64))
+            ])
+        }).collect()));
+    }
+
+    Json::Object(columns)
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, TextError> {
+    match json.find(key) {
+        Some(value) => Ok(value),
+        None => invalid(&format!("missing {}", key))
+    }
+}
+
+fn u64_field(json: &Json, key: &str) -> Result<u64, TextError> {
+    match try!(field(json, key)).as_u64() {
+        Some(value) => Ok(value),
+        None => invalid(&format!("{} is not an unsigned integer", key))
+    }
+}
+
+fn u32_field(json: &Json, key: &str) -> Result<u32, TextError> {
+    match try!(u64_field(json, key)) {
+        value if value <= !0u32 as u64 => Ok(value as u32),
+        _ => invalid(&format!("{} is out of range", key))
+    }
+}
+
+fn str_field<'a>(json: &'a Json, key: &str) -> Result<&'a str, TextError> {
+    match try!(field(json, key)).as_string() {
+        Some(value) => Ok(value),
+        None => invalid(&format!("{} is not a string", key))
+    }
+}
+
+fn array_field<'a>(json: &'a Json, key: &str) -> Result<&'a [Json], TextError> {
+    match try!(field(json, key)).as_array() {
+        Some(value) => Ok(&value[..]),
+        None => invalid(&format!("{} is not an array", key))
+    }
+}
+
+fn uuid_field(json: &Json, key: &str) -> Result<Uuid, TextError> {
+    let s = try!(str_field(json, key));
+    match Uuid::parse_str(s) {
+        Ok(uuid) => Ok(uuid),
+        Err(_) => invalid(&format!("{} is not a uuid", key))
+    }
+}
+
+/// Read the rows of a column if the column is in `columns`
+fn rows<T, F>(columns: &Json, name: &str, mut f: F) -> Result<Option<Vec<T>>, TextError>
+    where F: FnMut(&Json) -> Result<T, TextError>
+{
+    if columns.find(name).is_none() {
+        return Ok(None);
+    }
+    let mut rows = Vec::new();
+    for row in try!(array_field(columns, name)) {
+        rows.push(try!(f(row)));
+    }
+    Ok(Some(rows))
+}
+
+/// Parse the text written by `to_json`
+pub fn parse(text: &str) -> Result<Json, TextError> {
+    Ok(try!(Json::from_str(text)))
+}
+
+/// Write the hairball described by `json` to `path`, see `to_json`. The
+/// `path` of a local entity is only used to describe it and is ignored,
+/// the `name` and `parent` are used instead. The hairball is validated
+/// when it is closed. It is written next to `path` and only replaces
+/// `path` once it is complete, so `path` is left alone on an error.
+pub fn from_json<P>(json: &Json, path: P) -> Result<(), TextError>
+    where P: AsRef<Path>
+{
+    let uuid = try!(uuid_field(json, "uuid"));
+    let mut tmp = OsString::from(path.as_ref());
+    tmp.push(".tmp");
+
+    let mut builder = try!(Builder::new_with_uuid(&tmp, uuid));
+    let written = write_json(json, &mut builder);
+    if let Err(err) = written {
+        drop(builder);
+        let _ = std::fs::remove_file(&tmp);
+        return Err(err);
+    }
+    if let Err(err) = builder.close() {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(TextError::Hairball(err));
+    }
+    Ok(try!(std::fs::rename(&tmp, path).map_err(hairball::Error::Io)))
+}
+
+/// internal function that adds everything described by `json` to `builder`
+fn write_json(json: &Json, builder: &mut Builder) -> Result<(), TextError> {
+    if json.find("revision").is_some() {
+        builder.set_revision(try!(u64_field(json, "revision")));
+    }
+
+    for e in try!(array_field(json, "entities")) {
+        let guid = match e.find("guid") {
+            Some(_) => Some(try!(uuid_field(e, "guid"))),
+            None => None
+        };
+        if e.find("file").is_some() {
+            let file = try!(uuid_field(e, "file"));
+            let external = match (e.find("path"), guid) {
+                (Some(_), Some(guid)) => {
+                    ExternalEntity::new(file, try!(str_field(e, "path")).to_owned()).guid(guid)
+                }
+                (Some(_), None) => ExternalEntity::new(file, try!(str_field(e, "path")).to_owned()),
+                (None, Some(guid)) => ExternalEntity::by_guid(file, guid),
+                (None, None) => return invalid("external entity has no path or guid")
+            };
+            builder.add_external_entity(external);
+        } else {
+            builder.add_entity(LocalEntity {
+                name: match e.find("name") {
+                    Some(_) => Some(try!(str_field(e, "name")).to_owned()),
+                    None => None
+                },
+                parent: match e.find("parent") {
+                    Some(_) => Some(try!(u32_field(e, "parent"))),
+                    None => None
+                },
+                guid: guid
+            });
+        }
+    }
+
+    if let Some(pins) = json.find("pins").and_then(|p| p.as_object()) {
+        for (file, pin) in pins {
+            let file = match Uuid::parse_str(file) {
+                Ok(file) => file,
+                Err(_) => return invalid("pin is not for a uuid")
+            };
+            builder.add_pin(file, Pin {
+                hash: try!(u64_field(pin, "hash")),
+                revision: try!(u64_field(pin, "revision"))
+            });
+        }
+    }
+
+    let columns = try!(field(json, "columns"));
+
+    let meshes = try!(rows(columns, hairball_mesh::COLUMN_NAME, |row| {
+        let mut mesh: OwnedMesh = Vec::new();
+        for vb in try!(array_field(row, "buffers")) {
+            let mut attributes = Vec::new();
+            for a in try!(array_field(vb, "attributes")) {
+                let count = try!(u32_field(a, "count"));
+                if count > 255 {
+                    return invalid("attribute count is out of range");
+                }
+                match hairball_mesh::attribute(try!(str_field(a, "name")).to_owned(),
+                                               try!(str_field(a, "type")),
+                                               count as u8) {
+                    Some(a) => attributes.push(a),
+                    None => return invalid("unknown attribute type")
+                }
+            }
+            let data = match try!(str_field(vb, "data")).from_hex() {
+                Ok(data) => data,
+                Err(_) => return invalid("vertex data is not hex")
+            };
+            match Interlaced::new(attributes, data) {
+                Ok(vb) => mesh.push(vb),
+                Err(_) => return invalid("vertex data does not match the attributes")
+            }
+        }
+        Ok((try!(u32_field(row, "entity")), mesh))
+    }));
+    if let Some(meshes) = meshes {
+        hairball_mesh::write_owned(builder, &meshes[..]);
+    }
+
+    let indices = try!(rows(columns, hairball_mesh_index::COLUMN_NAME, |row| {
+        let mut index = Vec::new();
+        for i in try!(array_field(row, "index")) {
+            match i.as_u64() {
+                Some(i) if i <= !0u32 as u64 => index.push(i as u32),
+                _ => return invalid("index is not an unsigned integer")
+            }
+        }
+        Ok((try!(u32_field(row, "entity")), index))
+    }));
+    if let Some(indices) = indices {
+        hairball_mesh_index::write_owned(builder, &indices[..]);
+    }
+
+    let materials = try!(rows(columns, hairball_material::COLUMN_NAME, |row| {
+        let component = match try!(str_field(row, "component")) {
+            "ambient" => Component::Ambient,
+            "diffuse" => Component::Diffuse,
+            "specular" => Component::Specular,
+            _ => return invalid("unknown material component")
+        };
+        let value = if row.find("texture").is_some() {
+            Value::Texture(try!(u32_field(row, "texture")))
+        } else {
+            let c: Vec<f32> = try!(array_field(row, "color")).iter()
+                .filter_map(|x| x.as_f64())
+                .map(|x| x as f32)
+                .collect();
+            if c.len() != 4 {
+                return invalid("color does not have 4 components");
+            }
+            Value::Color([c[0], c[1], c[2], c[3]])
+        };
+        Ok((try!(u32_field(row, "entity")), component, value))
+    }));
+    if let Some(materials) = materials {
+        hairball_material::write(builder, &materials[..]);
+    }
+
+    let geometries = try!(rows(columns, hairball_geometry::COLUMN_NAME, |row| {
+        Ok((try!(u32_field(row, "entity")), Geometry {
+            mesh: try!(u32_field(row, "mesh")),
+            start: try!(u32_field(row, "start")),
+            length: try!(u32_field(row, "length"))
+        }))
+    }));
+    if let Some(geometries) = geometries {
+        hairball_geometry::write(builder, &geometries[..]);
+    }
+
+    let bindings = try!(rows(columns, hairball_draw_binding::COLUMN_NAME, |row| {
+        Ok((try!(u32_field(row, "entity")), DrawBinding {
+            geometry: try!(u32_field(row, "geometry")),
+            material: try!(u32_field(row, "material"))
+        }))
+    }));
+    if let Some(bindings) = bindings {
+        hairball_draw_binding::write(builder, &bindings[..]);
+    }
+
+    if let Some(tombstones) = json.find("tombstones").and_then(|t| t.as_object()) {
+        for (column, entities) in tombstones {
+            let entities = match entities.as_array() {
+                Some(entities) => entities,
+                None => return invalid("tombstones are not an array")
+            };
+            let mut ids = Vec::new();
+            for e in entities {
+                match e.as_u64() {
+                    Some(e) if e <= !0u32 as u64 => ids.push(e as u32),
+                    _ => return invalid("tombstone is not an unsigned integer")
+                }
+            }
+            try!(builder.delete_rows(column, &ids[..]).map_err(hairball::Error::Capnp));
+        }
+    }
+
+    Ok(())
+}
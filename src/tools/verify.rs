@@ -0,0 +1,69 @@
+use std::fmt;
+
+use hairball::{Reader, Invalid, RowError, RowCause};
+use hairball_mesh;
+use hairball_mesh_index;
+use hairball_material;
+use hairball_geometry;
+use hairball_draw_binding;
+
+/// A problem found by `verify`
+#[derive(Debug)]
+pub enum Problem {
+    /// The entities or the references of a column are inconsistent
+    Invalid(Invalid),
+    /// A row of a known column could not be read
+    Row(RowError)
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::Invalid(Invalid::ParentOutOfRange { entity, parent }) => {
+                write!(f, "entity {} has parent {} which does not exist", entity, parent)
+            }
+            Problem::Invalid(Invalid::ParentCycle(entity)) => {
+                write!(f, "entity {} is it's own ancestor", entity)
+            }
+            Problem::Invalid(Invalid::DanglingReference { ref column, row, entity }) => {
+                write!(f, "{} row {}: entity {} does not exist", column, row, entity)
+            }
+            Problem::Row(ref err) => write!(f, "{}", err)
+        }
+    }
+}
+
+/// Check the entities of `reader` and every row of the known columns.
+/// Returns every problem that was found, a valid hairball has none.
+pub fn verify(reader: &Reader) -> Vec<Problem> {
+    let mut invalid = reader.validate();
+    invalid.extend(hairball_mesh::validate(reader).into_iter());
+    invalid.extend(hairball_mesh_index::validate(reader).into_iter());
+    invalid.extend(hairball_material::validate(reader).into_iter());
+    invalid.extend(hairball_geometry::validate(reader).into_iter());
+    invalid.extend(hairball_draw_binding::validate(reader).into_iter());
+    let mut problems: Vec<Problem> = invalid.into_iter().map(Problem::Invalid).collect();
+
+    // a row that references a missing entity was reported by `validate`,
+    // only the rows that could not be decoded are added
+    let mapping = reader.into_mapping(|i| i as u32);
+    macro_rules! strict {
+        ($column:ident) => {
+            if let Some(rows) = $column::read(&mapping) {
+                for err in rows.strict().filter_map(|row| row.err()) {
+                    match err.cause {
+                        RowCause::EntityOutOfRange(_) => (),
+                        _ => problems.push(Problem::Row(err))
+                    }
+                }
+            }
+        }
+    }
+    strict!(hairball_mesh);
+    strict!(hairball_mesh_index);
+    strict!(hairball_material);
+    strict!(hairball_geometry);
+    strict!(hairball_draw_binding);
+
+    problems
+}
@@ -1,8 +1,14 @@
 extern crate uuid;
 extern crate hairball;
 extern crate capnp;
+extern crate hairball_material;
+extern crate hairball_mesh_index;
+extern crate hairball_geometry;
+extern crate hairball_draw_binding;
+extern crate hairball_tools;
+extern crate hairball_ffi;
 
-use hairball::{Reader, Builder, LocalEntity, ExternalEntity, file_uuid};
+use hairball::{Reader, Builder, LocalEntity, Entity, ExternalEntity, Overlay, Policy, Library, file_uuid};
 
 
 #[test]
@@ -14,7 +20,7 @@ fn eid_0_to_10_write() {
             LocalEntity::named(format!("{}", i))
         );
     }
-    hairball.close();
+    hairball.close().unwrap();
 
     // Read
     let hairball = Reader::read("hairballs/0..10.hairball").unwrap();
@@ -35,7 +41,7 @@ fn eid_0_to_140_000_write() {
             LocalEntity::named(format!("{}", i))
         );
     }
-    hairball.close();
+    hairball.close().unwrap();
 
     let hairball = Reader::read("hairballs/0..140_000.hairball").unwrap();
     assert_eq!(hairball.entities_len(), 140_000);
@@ -59,7 +65,7 @@ fn parent_list() {
         parent = Some(hairball.add_entity(e));
     }
 
-    hairball.close();
+    hairball.close().unwrap();
 
     let hairball = Reader::read("hairballs/parent_list.hairball").unwrap();
     assert_eq!(hairball.entities_len(), 10);
@@ -85,7 +91,7 @@ fn write_external() {
         );
     }
 
-    hairball.close();
+    hairball.close().unwrap();
 
     let hairball = Reader::read("hairballs/external.hairball").unwrap();
     assert_eq!(hairball.external_len(), 2);
@@ -107,7 +113,7 @@ fn write_external() {
 fn read_uuid() {
     let hairball = Builder::new("hairballs/uuid.hairball").unwrap();
     let uuid = hairball.uuid();
-    hairball.close();
+    hairball.close().unwrap();
 
     let hairball = Reader::read("hairballs/uuid.hairball").unwrap();
     assert_eq!(uuid, hairball.uuid());
@@ -130,7 +136,7 @@ fn columns() {
         let text = builder.get_as::<capnp::text::Reader>().unwrap();
         assert_eq!(s, text);
     }
-    hairball.close();
+    hairball.close().unwrap();
 
     let hairball = Reader::read("hairballs/column.hairball").unwrap();
     for i in 0..1_000 {
@@ -141,3 +147,1313 @@ fn columns() {
         assert_eq!(&s, text);
     }
 }
+
+#[test]
+fn prefetch_column() {
+    let mut hairball = Builder::new("hairballs/prefetch.hairball").unwrap();
+    for i in 0..10 {
+        let builder = hairball.column(&format!("column_{}", i)).unwrap();
+        let s = format!("column_{} \\o/", i);
+        let mut text = builder.initn_as::<capnp::text::Builder>(s.len() as u32);
+        text.push_str(&s);
+    }
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/prefetch.hairball").unwrap();
+    assert!(hairball.prefetch("column_5"));
+    assert!(!hairball.prefetch("column_10"));
+
+    let text = hairball.column("column_5").unwrap()
+        .get_as::<capnp::text::Reader>().unwrap();
+    assert_eq!("column_5 \\o/", text);
+}
+
+#[test]
+fn copy_column() {
+    let mut hairball = Builder::new("hairballs/copy_column_src.hairball").unwrap();
+    for i in 0..3 {
+        let builder = hairball.column(&format!("column_{}", i)).unwrap();
+        let s = format!("column_{} \\o/", i);
+        let mut text = builder.initn_as::<capnp::text::Builder>(s.len() as u32);
+        text.push_str(&s);
+    }
+    hairball.close().unwrap();
+
+    let src = Reader::read("hairballs/copy_column_src.hairball").unwrap();
+    assert_eq!(vec!["column_0", "column_1", "column_2"], src.column_names());
+
+    // strip column_1 by only copying the others
+    let mut hairball = Builder::new("hairballs/copy_column_dst.hairball").unwrap();
+    assert!(hairball.copy_column(&src, "column_0").unwrap());
+    assert!(hairball.copy_column(&src, "column_2").unwrap());
+    assert!(!hairball.copy_column(&src, "column_3").unwrap());
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/copy_column_dst.hairball").unwrap();
+    assert_eq!(vec!["column_0", "column_2"], hairball.column_names());
+    assert!(hairball.column("column_1").is_none());
+    for &i in &[0, 2] {
+        let text = hairball.column(&format!("column_{}", i)).unwrap()
+            .get_as::<capnp::text::Reader>().unwrap();
+        assert_eq!(format!("column_{} \\o/", i), text);
+    }
+
+    // copying a column again replaces it and reuses it's segments
+    let mut hairball = Builder::new("hairballs/copy_column_twice.hairball").unwrap();
+    assert!(hairball.copy_column(&src, "column_0").unwrap());
+    assert!(hairball.copy_column(&src, "column_2").unwrap());
+    assert!(hairball.copy_column(&src, "column_0").unwrap());
+    hairball.close().unwrap();
+
+    assert_eq!(std::fs::metadata("hairballs/copy_column_dst.hairball").unwrap().len(),
+               std::fs::metadata("hairballs/copy_column_twice.hairball").unwrap().len());
+    let hairball = Reader::read("hairballs/copy_column_twice.hairball").unwrap();
+    assert_eq!(vec!["column_2", "column_0"], hairball.column_names());
+}
+
+#[test]
+fn parallel_column_writers() {
+    use hairball::Columns;
+
+    let mut hairball = Builder::new("hairballs/parallel_columns.hairball").unwrap();
+    let threads: Vec<_> = (0..4).map(|i| {
+        let mut writer = hairball.column_writer(&format!("column_{}", i));
+        std::thread::spawn(move || {
+            {
+                let name = writer.name().to_owned();
+                let builder = writer.column(&name).unwrap();
+                let s = format!("column_{} \\o/", i);
+                let mut text = builder.initn_as::<capnp::text::Builder>(s.len() as u32);
+                text.push_str(&s);
+            }
+            writer
+        })
+    }).collect();
+
+    for t in threads {
+        hairball.commit(t.join().unwrap());
+    }
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/parallel_columns.hairball").unwrap();
+    for i in 0..4 {
+        let text = hairball.column(&format!("column_{}", i)).unwrap()
+            .get_as::<capnp::text::Reader>().unwrap();
+        assert_eq!(format!("column_{} \\o/", i), text);
+    }
+}
+
+#[test]
+fn merge() {
+    use hairball_geometry::Geometry;
+    use hairball_draw_binding::DrawBinding;
+
+    let mut hairball = Builder::new("hairballs/merge_a.hairball").unwrap();
+    let a_uuid = hairball.uuid();
+    let geometry = hairball.add_entity(LocalEntity::named("geometry".to_owned()));
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()).parent(geometry));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    hairball.close().unwrap();
+
+    let mut hairball = Builder::new("hairballs/merge_b.hairball").unwrap();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let cube = hairball.add_external_entity(ExternalEntity::new(a_uuid, "geometry/cube".to_owned()));
+    hairball.add_external_entity(ExternalEntity::new(a_uuid, "geometry/sphere".to_owned()));
+    hairball_draw_binding::write(&mut hairball, &[
+        (cube, DrawBinding{ geometry: cube, material: material })
+    ]);
+    hairball.close().unwrap();
+
+    let src = vec![
+        Reader::read("hairballs/merge_a.hairball").unwrap(),
+        Reader::read("hairballs/merge_b.hairball").unwrap()
+    ];
+    let mut hairball = Builder::new("hairballs/merge.hairball").unwrap();
+    let roots = hairball_tools::merge(&src[..], &mut hairball).unwrap();
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/merge.hairball").unwrap();
+    assert_eq!(roots, vec![0, 3]);
+    assert_eq!(hairball.entities_len(), 6);
+    let a = a_uuid.to_hyphenated_string();
+    let b = src[1].uuid().to_hyphenated_string();
+    assert_eq!(Some(format!("{}/geometry/cube", a)), hairball.path(2));
+    assert_eq!(Some(format!("{}/material", b)), hairball.path(4));
+    let e = hairball.get_entity(5).unwrap();
+    assert_eq!(Some(&a_uuid), e.file());
+    assert_eq!(Some("geometry/sphere"), e.name());
+
+    let mapping = hairball.into_mapping(|i| i as u32);
+    let geometry: Vec<_> = hairball_geometry::read(&mapping).unwrap()
+        .map(|(&id, g)| (id, *g.mesh, g.length))
+        .collect();
+    assert_eq!(vec![(2, 2, 36)], geometry);
+
+    // the external reference to the cube was replaced by the local cube
+    let bindings: Vec<_> = hairball_draw_binding::read(&mapping).unwrap()
+        .map(|(&id, b)| (id, *b.geometry, *b.material))
+        .collect();
+    assert_eq!(vec![(2, 2, 4)], bindings);
+
+    // the ids in a column that is not known cannot be remapped
+    let mut hairball = Builder::new("hairballs/merge_c.hairball").unwrap();
+    let builder = hairball.column("notes").unwrap();
+    builder.initn_as::<capnp::text::Builder>(0);
+    hairball.close().unwrap();
+
+    let src = vec![
+        Reader::read("hairballs/merge_a.hairball").unwrap(),
+        Reader::read("hairballs/merge_c.hairball").unwrap()
+    ];
+    let mut hairball = Builder::new("hairballs/merge_unknown.hairball").unwrap();
+    assert_eq!(Err(hairball_tools::MergeError::UnknownColumns(vec!["notes".to_owned()])),
+               hairball_tools::merge(&src[..], &mut hairball));
+    assert_eq!(0, hairball.entities_len());
+}
+
+#[test]
+fn extract() {
+    use hairball_geometry::Geometry;
+    use hairball_draw_binding::DrawBinding;
+
+    let mut hairball = Builder::new("hairballs/extract_src.hairball").unwrap();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    let blue = hairball.add_entity(LocalEntity::named("blue".to_owned()).parent(material));
+    let geometry = hairball.add_entity(LocalEntity::named("geometry".to_owned()));
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()).parent(geometry));
+    let sphere = hairball.add_entity(LocalEntity::named("sphere".to_owned()).parent(geometry));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 }),
+        (sphere, Geometry{ mesh: sphere, start: 0, length: 960 })
+    ]);
+    hairball_draw_binding::write(&mut hairball, &[
+        (cube, DrawBinding{ geometry: cube, material: red }),
+        (sphere, DrawBinding{ geometry: sphere, material: blue })
+    ]);
+    hairball.close().unwrap();
+
+    let src = Reader::read("hairballs/extract_src.hairball").unwrap();
+    let mut hairball = Builder::new("hairballs/extract.hairball").unwrap();
+    assert!(hairball_tools::extract(&src, 100, &mut hairball).is_none());
+    let cube = hairball_tools::extract(&src, cube, &mut hairball).unwrap();
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/extract.hairball").unwrap();
+    let paths: Vec<_> = (0..hairball.entities_len())
+        .map(|i| hairball.path(i).unwrap())
+        .collect();
+    assert_eq!(vec!["material", "material/red", "geometry", "geometry/cube"], paths);
+    assert_eq!(Some("geometry/cube".to_owned()), hairball.path(cube as usize));
+
+    let mapping = hairball.into_mapping(|i| i as u32);
+    let geometry: Vec<_> = hairball_geometry::read(&mapping).unwrap()
+        .map(|(&id, g)| (id, *g.mesh, g.length))
+        .collect();
+    assert_eq!(vec![(3, 3, 36)], geometry);
+    let bindings: Vec<_> = hairball_draw_binding::read(&mapping).unwrap()
+        .map(|(&id, b)| (id, *b.geometry, *b.material))
+        .collect();
+    assert_eq!(vec![(3, 3, 1)], bindings);
+}
+
+#[test]
+fn diff() {
+    use hairball_material::{Component, Value};
+    use hairball_tools::Change;
+
+    let mut hairball = Builder::new("hairballs/diff_old.hairball").unwrap();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0., 0., 1.]))
+    ]);
+    hairball.close().unwrap();
+
+    // same entities in a different order, with one added and a changed color
+    let mut hairball = Builder::new("hairballs/diff_new.hairball").unwrap();
+    hairball.add_entity(LocalEntity::named("sphere".to_owned()));
+    hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([0.5, 0., 0., 1.]))
+    ]);
+    hairball.close().unwrap();
+
+    let old = Reader::read("hairballs/diff_old.hairball").unwrap();
+    let new = Reader::read("hairballs/diff_new.hairball").unwrap();
+    assert!(hairball_tools::diff(&old, &old).is_empty());
+
+    let diff = hairball_tools::diff(&old, &new);
+    assert_eq!(2, diff.changes.len());
+    assert_eq!(Change::Added("entity", "sphere".to_owned(), "local".to_owned()), diff.changes[0]);
+    match diff.changes[1] {
+        Change::Changed("material", ref path, _, _) => assert_eq!("material/red", path),
+        ref c => panic!("unexpected change {}", c)
+    }
+}
+
+#[test]
+fn rebase() {
+    use hairball_material::{Component, Value};
+    use hairball_geometry::Geometry;
+
+    let mut hairball = Builder::new("hairballs/rebase_base.hairball").unwrap();
+    let base_uuid = hairball.uuid();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0., 0., 1.]))
+    ]);
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    hairball.close().unwrap();
+
+    // red is unchanged, blue was added and the cube was modified
+    let mut hairball = Builder::new("hairballs/rebase_derived.hairball").unwrap();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    let blue = hairball.add_entity(LocalEntity::named("blue".to_owned()).parent(material));
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0., 0., 1.])),
+        (blue, Component::Diffuse, Value::Color([0., 0., 1., 1.]))
+    ]);
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 24 })
+    ]);
+    hairball.close().unwrap();
+
+    let base = Reader::read("hairballs/rebase_base.hairball").unwrap();
+    let derived = Reader::read("hairballs/rebase_derived.hairball").unwrap();
+    let mut hairball = Builder::new("hairballs/rebase.hairball").unwrap();
+    assert_eq!(1, hairball_tools::rebase(&derived, &base, &mut hairball));
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/rebase.hairball").unwrap();
+    assert_eq!(4, hairball.entities_len());
+    let e = hairball.get_entity(red as usize).unwrap();
+    assert_eq!(Some(&base_uuid), e.file());
+    assert_eq!(Some("material/red"), e.name());
+    assert_eq!(Some("material/blue".to_owned()), hairball.path(blue as usize));
+
+    let mapping = hairball.into_mapping(|i| i as u32);
+    let materials: Vec<_> = hairball_material::read(&mapping).unwrap()
+        .map(|(&id, _, _)| id)
+        .collect();
+    assert_eq!(vec![blue], materials);
+    let geometry: Vec<_> = hairball_geometry::read(&mapping).unwrap()
+        .map(|(&id, g)| (id, g.length))
+        .collect();
+    assert_eq!(vec![(cube, 24)], geometry);
+}
+
+#[test]
+fn overlay() {
+    use hairball_material::{Component, Value};
+
+    let mut hairball = Builder::new("hairballs/overlay_base.hairball").unwrap();
+    let base_uuid = hairball.uuid();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    let blue = hairball.add_entity(LocalEntity::named("blue".to_owned()).parent(material));
+    let green = hairball.add_entity(LocalEntity::named("green".to_owned()).parent(material));
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0., 0., 1.])),
+        (blue, Component::Diffuse, Value::Color([0., 0., 1., 1.])),
+        (green, Component::Diffuse, Value::Color([0., 1., 0., 1.]))
+    ]);
+    hairball.close().unwrap();
+
+    // the patch changes red, deletes blue and leaves green alone
+    let mut hairball = Builder::new("hairballs/overlay_patch.hairball").unwrap();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    let blue = hairball.add_external_entity(ExternalEntity::new(base_uuid, "material/blue".to_owned()));
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([0.5, 0., 0., 1.])),
+        (red, Component::Specular, Value::Color([1., 1., 1., 1.]))
+    ]);
+    hairball.delete_rows(hairball_material::COLUMN_NAME, &[blue]).unwrap();
+    hairball.close().unwrap();
+
+    // the tombstones are not a column of their own
+    let patch = Reader::read("hairballs/overlay_patch.hairball").unwrap();
+    assert_eq!(vec![hairball_material::COLUMN_NAME], patch.column_names());
+    assert_eq!(vec![hairball_material::COLUMN_NAME], patch.tombstoned_columns());
+    assert_eq!(vec![blue], patch.tombstones(hairball_material::COLUMN_NAME));
+
+    let overlay = Overlay::new(vec![
+        Reader::read("hairballs/overlay_base.hairball").unwrap(),
+        Reader::read("hairballs/overlay_patch.hairball").unwrap()
+    ]);
+    let mapping = overlay.into_mapping(|path| path.to_owned());
+    let rows: Vec<_> = hairball_material::read_overlay(&mapping).unwrap()
+        .map(|(path, c, v)| (&path[..], format!("{:?} {:?}", c, v)))
+        .collect();
+
+    assert_eq!(vec![
+        ("material/red", format!("{:?} {:?}", Component::Diffuse, Value::Color([0.5, 0., 0., 1.]))),
+        ("material/red", format!("{:?} {:?}", Component::Specular, Value::Color([1., 1., 1., 1.]))),
+        ("material/green", format!("{:?} {:?}", Component::Diffuse, Value::Color([0., 1., 0., 1.])))
+    ], rows);
+}
+
+#[test]
+fn guids() {
+    let guid = uuid::Uuid::new_v4();
+    let mut hairball = Builder::new("hairballs/guid_a.hairball").unwrap();
+    let a_uuid = hairball.uuid();
+    let geometry = hairball.add_entity(LocalEntity::named("geometry".to_owned()));
+    hairball.add_entity(LocalEntity::named("cube".to_owned()).parent(geometry).guid(guid));
+    hairball.close().unwrap();
+
+    let mut hairball = Builder::new("hairballs/guid_b.hairball").unwrap();
+    hairball.add_external_entity(ExternalEntity::by_guid(a_uuid, guid));
+    hairball.close().unwrap();
+
+    let a = Reader::read("hairballs/guid_a.hairball").unwrap();
+    assert_eq!(Some(&guid), a.get_entity(1).unwrap().guid());
+    // only entities that were given a guid have one
+    assert_eq!(None, a.get_entity(0).unwrap().guid());
+    assert_eq!(Some(1), a.find_guid(&guid));
+
+    let b = Reader::read("hairballs/guid_b.hairball").unwrap();
+    let e = b.get_entity(0).unwrap();
+    assert_eq!(None, e.name());
+    assert_eq!(Some(&guid), e.guid());
+
+    // merging resolves the reference using the guid
+    let src = vec![a, b];
+    let mut hairball = Builder::new("hairballs/guid_merge.hairball").unwrap();
+    hairball_tools::merge(&src[..], &mut hairball);
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/guid_merge.hairball").unwrap();
+    assert_eq!(4, hairball.entities_len());
+    assert_eq!(Some(2), hairball.find_guid(&guid));
+}
+
+#[test]
+fn pinned_external() {
+    let mut hairball = Builder::new("hairballs/pin_base.hairball").unwrap();
+    let base_uuid = hairball.uuid();
+    hairball.set_revision(1);
+    hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    hairball.close().unwrap();
+
+    let base = Reader::read("hairballs/pin_base.hairball").unwrap();
+    assert_eq!(1, base.revision());
+    let mut hairball = Builder::new("hairballs/pin_save.hairball").unwrap();
+    hairball.add_external_entity(ExternalEntity::new(base_uuid, "cube".to_owned()));
+    hairball.pin(&base).unwrap();
+    hairball.close().unwrap();
+
+    let save = Reader::read("hairballs/pin_save.hairball").unwrap();
+    assert_eq!(Some(1), save.pin(0).map(|p| p.revision));
+    assert!(save.pin_matches(&base).unwrap());
+    assert!(save.check_pin(&base, Policy::Strict).is_ok());
+
+    // rewrite the base with the same uuid
+    drop(base);
+    let mut hairball = Builder::new_with_uuid("hairballs/pin_base.hairball", base_uuid).unwrap();
+    hairball.set_revision(2);
+    hairball.add_entity(LocalEntity::named("sphere".to_owned()));
+    hairball.close().unwrap();
+
+    let base = Reader::read("hairballs/pin_base.hairball").unwrap();
+    assert!(!save.pin_matches(&base).unwrap());
+    assert!(save.check_pin(&base, Policy::Strict).is_err());
+    match save.check_pin(&base, Policy::Warn) {
+        Ok(Some(hairball::Error::PinMismatch(uuid))) => assert_eq!(base_uuid, uuid),
+        r => panic!("expected a warning, got {:?}", r)
+    }
+    assert!(save.check_pin(&base, Policy::Ignore).unwrap().is_none());
+    assert!(Overlay::with_policy(vec![base, save], Policy::Strict).is_err());
+
+    let base = Reader::read("hairballs/pin_base.hairball").unwrap();
+    let save = Reader::read("hairballs/pin_save.hairball").unwrap();
+    let overlay = Overlay::with_policy(vec![base, save], Policy::Warn).unwrap();
+    assert_eq!(1, overlay.warnings().len());
+}
+
+#[test]
+fn linked_material() {
+    use hairball_material::{Component, Value};
+    use hairball_draw_binding::DrawBinding;
+
+    let mut hairball = Builder::new("hairballs/linked_library.hairball").unwrap();
+    let library_uuid = hairball.uuid();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material));
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0., 0., 1.]))
+    ]);
+    hairball.close().unwrap();
+
+    let mut hairball = Builder::new("hairballs/linked_scene.hairball").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    let red = hairball.add_external_entity(ExternalEntity::new(library_uuid, "material/red".to_owned()));
+    hairball_draw_binding::write(&mut hairball, &[
+        (cube, DrawBinding{ geometry: cube, material: red })
+    ]);
+    hairball.close().unwrap();
+
+    let mut library = Library::new();
+    library.add(Reader::read("hairballs/linked_library.hairball").unwrap());
+    let scene = Reader::read("hairballs/linked_scene.hairball").unwrap();
+
+    let bindings: Vec<_> = hairball_draw_binding::read_linked(&library, &scene).unwrap().collect();
+    assert_eq!(1, bindings.len());
+    let (id, binding) = bindings[0];
+    assert_eq!(cube, id);
+    assert_eq!(scene.uuid(), binding.geometry.reader.uuid());
+    assert_eq!(library_uuid, binding.material.reader.uuid());
+    assert_eq!(1, binding.material.entity);
+
+    let material = hairball_material::lookup(&binding.material);
+    assert_eq!(1, material.len());
+    match material[0] {
+        (Component::Diffuse, Value::Color(c)) => assert_eq!([1., 0., 0., 1.], c),
+        _ => panic!("unexpected material")
+    }
+
+    // without the library the material can not be resolved
+    let empty = Library::new();
+    assert_eq!(0, hairball_draw_binding::read_linked(&empty, &scene).unwrap().count());
+}
+
+#[test]
+fn lookup_sorted_rows() {
+    use hairball::Link;
+    use hairball_geometry::Geometry;
+    use hairball_material::{Component, Value};
+
+    // the rows are written out of order, `write` sorts them
+    let mut hairball = Builder::new("hairballs/lookup_sorted.hairball").unwrap();
+    for _ in 0..8 {
+        hairball.add_entity(LocalEntity::anonymous());
+    }
+    let geometry: Vec<_> = (0..7).rev()
+        .map(|e| (e, Geometry{ mesh: e, start: e * 3, length: 3 }))
+        .collect();
+    hairball_geometry::write(&mut hairball, &geometry);
+    hairball_material::write(&mut hairball, &[
+        (5, Component::Diffuse, Value::Texture(1)),
+        (2, Component::Ambient, Value::Texture(0)),
+        (5, Component::Specular, Value::Texture(2))
+    ]);
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/lookup_sorted.hairball").unwrap();
+    assert!(hairball.column_sorted(hairball_geometry::COLUMN_NAME));
+    for e in 0..7 {
+        let g = hairball_geometry::lookup(&Link { reader: &hairball, entity: e }).unwrap();
+        assert_eq!((e as u32, e as u32 * 3), (g.mesh, g.start));
+    }
+    assert!(hairball_geometry::lookup(&Link { reader: &hairball, entity: 7 }).is_none());
+
+    let material = hairball_material::lookup(&Link { reader: &hairball, entity: 5 });
+    assert_eq!(2, material.len());
+    match (material[0], material[1]) {
+        ((Component::Diffuse, Value::Texture(1)), (Component::Specular, Value::Texture(2))) => (),
+        _ => panic!("unexpected material")
+    }
+    assert_eq!(0, hairball_material::lookup(&Link { reader: &hairball, entity: 4 }).len());
+}
+
+#[test]
+fn entity_mapping() {
+    let mut hairball = Builder::new("hairballs/entity_mapping_a.hairball").unwrap();
+    let a_uuid = hairball.uuid();
+    // the child is written before it's parent
+    hairball.add_entity(LocalEntity::named("cube".to_owned()).parent(1));
+    hairball.add_entity(LocalEntity::named("geometry".to_owned()));
+    hairball.close().unwrap();
+
+    let mut hairball = Builder::new("hairballs/entity_mapping_b.hairball").unwrap();
+    hairball.add_entity(LocalEntity::named("scene".to_owned()));
+    hairball.add_external_entity(ExternalEntity::new(a_uuid, "geometry/cube".to_owned()));
+    hairball.close().unwrap();
+
+    let a = Reader::read("hairballs/entity_mapping_a.hairball").unwrap();
+    let b = Reader::read("hairballs/entity_mapping_b.hairball").unwrap();
+    assert_eq!(Some(0), a.lookup("geometry/cube"));
+
+    let a_mapping = a.into_entity_mapping(None, |_, e, parent, _| {
+        let name = e.name().unwrap().to_owned();
+        Some(match parent {
+            Some(p) => format!("{}/{}", p, name),
+            None => name
+        })
+    });
+    assert_eq!(Some(&"geometry/cube".to_owned()), a_mapping.entity(0));
+
+    let mut library = Library::new();
+    library.add(Reader::read("hairballs/entity_mapping_a.hairball").unwrap());
+    let mut targets = Vec::new();
+    let mut b_mapping = b.into_entity_mapping(Some(&library), |i, e, _, target| {
+        targets.push((i, target.map(|t| t.entity)));
+        match e {
+            Entity::Local(l) => Some(l.name.unwrap().to_owned()),
+            Entity::External(_) => None
+        }
+    });
+    assert_eq!(vec![(0, None), (1, Some(0))], targets);
+    assert_eq!(None, b_mapping.entity(1));
+
+    // the external entity uses the mapping of the entity it references
+    b_mapping.link(&a_mapping);
+    assert_eq!(Some(&"geometry/cube".to_owned()), b_mapping.entity(1));
+}
+
+#[test]
+fn builder_mapping_externals() {
+    let mut hairball = Builder::new("hairballs/builder_mapping_a.hairball").unwrap();
+    let a_uuid = hairball.uuid();
+    let geometry = hairball.add_entity(LocalEntity::named("geometry".to_owned()));
+    hairball.add_entity(LocalEntity::named("cube".to_owned()).parent(geometry));
+    hairball.close().unwrap();
+
+    // the engine entities of the loaded hairball are 100 and 101
+    let a = Reader::read("hairballs/builder_mapping_a.hairball").unwrap();
+    let loaded = vec![a.into_mapping(|i| 100 + i as u32)];
+
+    let mut hairball = Builder::new("hairballs/builder_mapping_b.hairball").unwrap();
+    {
+        let mut mapping = hairball.mapping();
+        let scene = mapping.add_entity(0u32, LocalEntity::named("scene".to_owned()));
+        assert_eq!(Some(1), mapping.add_child(1, LocalEntity::named("camera".to_owned()), &0));
+        assert_eq!(None, mapping.add_child(2, LocalEntity::named("light".to_owned()), &3));
+        assert_eq!(Some(scene), mapping.reference(&0, &loaded[..]));
+        assert_eq!(Some(2), mapping.reference(&101, &loaded[..]));
+        assert_eq!(Some(2), mapping.reference(&101, &loaded[..]));
+        assert_eq!(None, mapping.reference(&5, &loaded[..]));
+    }
+    hairball.close().unwrap();
+
+    let b = Reader::read("hairballs/builder_mapping_b.hairball").unwrap();
+    assert_eq!(3, b.entities_len());
+    assert_eq!(Some("scene/camera".to_owned()), b.path(1));
+    let cube = b.get_entity(2).unwrap();
+    assert_eq!(Some(&a_uuid), cube.file());
+    assert_eq!(Some("geometry/cube"), cube.name());
+}
+
+#[test]
+fn add_entity_at_path() {
+    let mut hairball = Builder::new("hairballs/entity_at_path.hairball").unwrap();
+    let geometry = hairball.add_entity(LocalEntity::named("geometry".to_owned()));
+    let body = hairball.add_entity_at_path("geometry/cube/body").unwrap();
+    let lid = hairball.add_entity_at_path("geometry/cube/lid").unwrap();
+    assert_eq!(4, hairball.entities_len());
+    assert!(hairball.add_entity_at_path("geometry/cube").is_err());
+    assert!(hairball.add_entity_at_path("geometry/cube/body").is_err());
+    assert!(hairball.add_entity_at_path("/").is_err());
+    assert_eq!(4, hairball.entities_len());
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/entity_at_path.hairball").unwrap();
+    assert_eq!(Some(geometry), hairball.get_entity(1).unwrap().parent());
+    assert_eq!(Some("geometry/cube/body".to_owned()), hairball.path(body as usize));
+    assert_eq!(Some("geometry/cube/lid".to_owned()), hairball.path(lid as usize));
+}
+
+#[test]
+fn validate() {
+    use hairball::Invalid;
+    use hairball_geometry::Geometry;
+
+    fn build() -> Builder {
+        let mut hairball = Builder::new("hairballs/validate.hairball").unwrap();
+        let a = hairball.add_entity(LocalEntity::named("a".to_owned()).parent(1));
+        hairball.add_entity(LocalEntity::named("b".to_owned()).parent(a));
+        hairball.add_entity(LocalEntity::named("c".to_owned()).parent(7));
+        hairball.add_entity(LocalEntity::named("d".to_owned()).parent(3));
+        hairball_geometry::write(&mut hairball, &[
+            (a, Geometry{ mesh: a, start: 0, length: 3 }),
+            (a, Geometry{ mesh: 9, start: 0, length: 3 }),
+            (a, Geometry{ mesh: 11, start: 0, length: 3 })
+        ]);
+        hairball
+    }
+
+    let expected = vec![
+        Invalid::ParentCycle(0),
+        Invalid::ParentOutOfRange { entity: 2, parent: 7 },
+        Invalid::ParentCycle(3),
+        Invalid::DanglingReference {
+            column: hairball_geometry::COLUMN_NAME.to_owned(),
+            row: 1,
+            entity: 9
+        },
+        Invalid::DanglingReference {
+            column: hairball_geometry::COLUMN_NAME.to_owned(),
+            row: 2,
+            entity: 11
+        }
+    ];
+    let hairball = build();
+    assert_eq!(expected, hairball.validate());
+    match hairball.close() {
+        Err(hairball::Error::Invalid(invalid)) => assert_eq!(expected, invalid),
+        _ => panic!("expected the hairball to be invalid")
+    }
+    // an invalid hairball is not written
+    assert!(std::fs::metadata("hairballs/validate.hairball").is_err());
+
+    // a reference to an entity that is added later is valid
+    let mut hairball = Builder::new("hairballs/validate_later.hairball").unwrap();
+    hairball_geometry::write(&mut hairball, &[
+        (0, Geometry{ mesh: 1, start: 0, length: 3 })
+    ]);
+    hairball.add_entity(LocalEntity::named("a".to_owned()));
+    hairball.add_entity(LocalEntity::named("b".to_owned()));
+    assert_eq!(Vec::<Invalid>::new(), hairball.validate());
+    hairball.close().unwrap();
+
+    build().close_unchecked().unwrap();
+
+    // the reader finds the same problems
+    let hairball = Reader::read("hairballs/validate.hairball").unwrap();
+    let mut invalid = hairball.validate();
+    invalid.extend(hairball_geometry::validate(&hairball).into_iter());
+    assert_eq!(expected, invalid);
+}
+
+#[test]
+fn column_get() {
+    use hairball_material::{Component, Value};
+
+    let mut hairball = Builder::new("hairballs/column_get.hairball").unwrap();
+    let ids: Vec<u32> = (0..100).map(|i| {
+        hairball.add_entity(LocalEntity::named(format!("{}", i)))
+    }).collect();
+    // written in reverse, each entity has two rows
+    let rows: Vec<_> = ids.iter().rev().flat_map(|&id| {
+        vec![
+            (id, Component::Diffuse, Value::Color([id as f32, 0., 0., 1.])),
+            (id, Component::Specular, Value::Color([0., id as f32, 0., 1.]))
+        ].into_iter()
+    }).collect();
+    hairball_material::write(&mut hairball, &rows[..]);
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/column_get.hairball").unwrap();
+    assert!(hairball.column_sorted(hairball_material::COLUMN_NAME));
+    let mapping = hairball.into_mapping(|i| i as u32);
+    let column = hairball_material::read(&mapping).unwrap();
+    assert!(column.sorted());
+
+    let all: Vec<u32> = hairball_material::read(&mapping).unwrap().map(|(&id, _, _)| id).collect();
+    let mut sorted = all.clone();
+    sorted.sort();
+    assert_eq!(sorted, all);
+
+    let rows: Vec<_> = column.get(42).collect();
+    assert_eq!(2, rows.len());
+    match rows[0] {
+        (&42, Component::Diffuse, Value::Color(c)) => assert_eq!([42., 0., 0., 1.], c),
+        _ => panic!("unexpected row")
+    }
+    match rows[1] {
+        (&42, Component::Specular, Value::Color(c)) => assert_eq!([0., 42., 0., 1.], c),
+        _ => panic!("unexpected row")
+    }
+    assert_eq!(0, column.get(100).count());
+}
+
+#[test]
+fn strict_reader() {
+    use hairball::RowCause;
+    use hairball_geometry::Geometry;
+
+    let mut hairball = Builder::new("hairballs/strict.hairball").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    let sphere = hairball.add_entity(LocalEntity::named("sphere".to_owned()));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 }),
+        (sphere, Geometry{ mesh: 5, start: 0, length: 960 })
+    ]);
+    hairball.close_unchecked().unwrap();
+
+    let hairball = Reader::read("hairballs/strict.hairball").unwrap();
+    let mapping = hairball.into_mapping(|i| i as u32);
+
+    // the bad row is skipped by the default reader
+    assert_eq!(1, hairball_geometry::read(&mapping).unwrap().count());
+
+    let rows: Vec<_> = hairball_geometry::read(&mapping).unwrap().strict().collect();
+    assert_eq!(2, rows.len());
+    assert!(rows[0].is_ok());
+    match rows[1] {
+        Err(ref err) => {
+            assert_eq!(hairball_geometry::COLUMN_NAME, err.column);
+            assert_eq!(1, err.row);
+            match err.cause {
+                RowCause::EntityOutOfRange(5) => (),
+                _ => panic!("unexpected cause {}", err)
+            }
+        }
+        Ok(_) => panic!("expected an error")
+    }
+}
+
+#[test]
+fn chunked_writer() {
+    use hairball_geometry::Geometry;
+
+    let mut hairball = Builder::new("hairballs/chunked.hairball").unwrap();
+    let ids: Vec<u32> = (0..10).map(|i| {
+        hairball.add_entity(LocalEntity::named(format!("{}", i)))
+    }).collect();
+
+    let mut writer = hairball.chunked_writer(
+        hairball_geometry::COLUMN_NAME, 3,
+        hairball_geometry::write::<hairball::ColumnWriter>
+    );
+    for &id in ids.iter().rev() {
+        writer.push((id, Geometry{ mesh: id, start: 0, length: id * 3 }));
+    }
+    assert_eq!(10, writer.len());
+    hairball.commit_chunks(writer);
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/chunked.hairball").unwrap();
+    assert_eq!(vec![hairball_geometry::COLUMN_NAME], hairball.column_names());
+    assert_eq!(4, hairball.chunks(hairball_geometry::COLUMN_NAME).len());
+    assert!(hairball.validate().is_empty());
+    assert!(hairball_geometry::validate(&hairball).is_empty());
+
+    let mapping = hairball.into_mapping(|i| i as u32);
+    let column = hairball_geometry::read(&mapping).unwrap();
+    assert!(column.sorted());
+    let mut all: Vec<u32> = hairball_geometry::read(&mapping).unwrap().map(|(&id, _)| id).collect();
+    all.sort();
+    assert_eq!(ids, all);
+
+    let rows: Vec<_> = column.get(7).collect();
+    assert_eq!(1, rows.len());
+    assert_eq!(21, rows[0].1.length);
+
+    // committing the column again replaces the rows and their references
+    let mut hairball = Builder::new("hairballs/chunked_replace.hairball").unwrap();
+    let a = hairball.add_entity(LocalEntity::named("a".to_owned()));
+    for &mesh in &[5, a] {
+        let mut writer = hairball.chunked_writer(
+            hairball_geometry::COLUMN_NAME, 1,
+            hairball_geometry::write::<hairball::ColumnWriter>
+        );
+        writer.push((a, Geometry{ mesh: mesh, start: 0, length: 3 }));
+        hairball.commit_chunks(writer);
+    }
+    assert!(hairball.validate().is_empty());
+
+    // the hairball is not written while a writer is still open
+    let writer = hairball.column_writer("open");
+    match hairball.close() {
+        Err(hairball::Error::OpenColumnWriters(1)) => (),
+        _ => panic!("expected a column writer to be open")
+    }
+    drop(writer);
+    assert!(std::fs::metadata("hairballs/chunked_replace.hairball").is_err());
+}
+
+#[test]
+fn append_rows() {
+    use hairball::Invalid;
+    use hairball_material::{Component, Value};
+    use hairball_geometry::Geometry;
+
+    let mut hairball = Builder::new("hairballs/append_rows.hairball").unwrap();
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()));
+    let blue = hairball.add_entity(LocalEntity::named("blue".to_owned()));
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    // one call per imported file
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0., 0., 1.]))
+    ]);
+    hairball_material::write(&mut hairball, &[
+        (blue, Component::Diffuse, Value::Color([0., 0., 1., 1.])),
+        (red, Component::Specular, Value::Color([1., 1., 1., 1.]))
+    ]);
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: 9, start: 36, length: 36 })
+    ]);
+    assert_eq!(vec![Invalid::DanglingReference {
+        column: hairball_geometry::COLUMN_NAME.to_owned(),
+        row: 1,
+        entity: 9
+    }], hairball.validate());
+    hairball.close_unchecked().unwrap();
+
+    let hairball = Reader::read("hairballs/append_rows.hairball").unwrap();
+    let mapping = hairball.into_mapping(|i| i as u32);
+    assert_eq!(3, hairball_material::read(&mapping).unwrap().count());
+    let column = hairball_material::read(&mapping).unwrap();
+    assert_eq!(2, column.get(red).count());
+    assert_eq!(1, column.get(blue).count());
+    assert_eq!(1, hairball_geometry::read(&mapping).unwrap().count());
+}
+
+#[test]
+fn embedded_schema() {
+    use capnp::traits::HasTypeId;
+    use hairball_geometry::Geometry;
+    use hairball_geometry::geometry_capnp;
+    use hairball_tools::DumpError;
+
+    let mut hairball = Builder::new("hairballs/schema.hairball").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    {
+        let builder = hairball.column("raw").unwrap();
+        let mut text = builder.initn_as::<capnp::text::Builder>(4);
+        text.push_str("text");
+    }
+    hairball.set_schema("raw", 42, Some(b"request"));
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/schema.hairball").unwrap();
+    let id = <geometry_capnp::column::Reader as HasTypeId>::type_id();
+    assert_eq!(Some(id), hairball.column_schema(hairball_geometry::COLUMN_NAME));
+    assert_eq!(hairball_geometry::schema(), hairball.schema(id));
+    assert_eq!(Some(42), hairball.column_schema("raw"));
+    assert_eq!(Some(&b"request"[..]), hairball.schema(42));
+    assert_eq!(None, hairball.schema(43));
+
+    match hairball_tools::dump(&hairball, "missing", &mut Vec::new()) {
+        Err(DumpError::NoColumn) => (),
+        _ => panic!("expected the column to be missing")
+    }
+}
+
+#[test]
+fn json_round_trip() {
+    use hairball_material::{Component, Value};
+    use hairball_geometry::Geometry;
+    use hairball_draw_binding::DrawBinding;
+
+    let red_guid = uuid::Uuid::new_v4();
+    let sphere_guid = uuid::Uuid::new_v4();
+    let mut hairball = Builder::new("hairballs/json.hairball").unwrap();
+    let material = hairball.add_entity(LocalEntity::named("material".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()).parent(material).guid(red_guid));
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    let sphere = hairball.add_external_entity(
+        ExternalEntity::new(uuid::Uuid::new_v4(), "geometry/sphere".to_owned()).guid(sphere_guid)
+    );
+    hairball.add_entity(LocalEntity::anonymous());
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0.25, 0., 1.])),
+        (red, Component::Ambient, Value::Texture(7))
+    ]);
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    hairball_draw_binding::write(&mut hairball, &[
+        (cube, DrawBinding{ geometry: cube, material: red }),
+        (sphere, DrawBinding{ geometry: sphere, material: red })
+    ]);
+    hairball.delete_rows(hairball_geometry::COLUMN_NAME, &[sphere]).unwrap();
+    {
+        let builder = hairball.column("notes").unwrap();
+        builder.initn_as::<capnp::text::Builder>(0);
+    }
+    hairball.set_revision(3);
+    hairball.close().unwrap();
+
+    let original = Reader::read("hairballs/json.hairball").unwrap();
+    assert_eq!(vec!["notes"], hairball_tools::skipped_columns(&original));
+    let text = hairball_tools::to_json(&original).pretty().to_string();
+    let json = hairball_tools::parse(&text).unwrap();
+    hairball_tools::from_json(&json, "hairballs/json_loaded.hairball").unwrap();
+
+    let loaded = Reader::read("hairballs/json_loaded.hairball").unwrap();
+    assert_eq!(original.uuid(), loaded.uuid());
+    assert_eq!(3, loaded.revision());
+    assert_eq!(Some(red as usize), loaded.find_guid(&red_guid));
+    let e = loaded.get_entity(sphere as usize).unwrap();
+    assert_eq!(Some("geometry/sphere"), e.name());
+    assert_eq!(Some(&sphere_guid), e.guid());
+    assert_eq!(vec![sphere], loaded.tombstones(hairball_geometry::COLUMN_NAME));
+    assert!(hairball_tools::skipped_columns(&loaded).is_empty());
+    assert!(hairball_tools::diff(&original, &loaded).is_empty());
+    assert_eq!(hairball_tools::to_json(&original), hairball_tools::to_json(&loaded));
+
+    match hairball_tools::parse("{\"uuid\": \"not a uuid\"}")
+        .and_then(|json| hairball_tools::from_json(&json, "hairballs/json_bad.hairball")) {
+        Err(hairball_tools::TextError::Invalid(_)) => (),
+        _ => panic!("expected the uuid to be invalid")
+    }
+
+    // a hairball that fails to load does not replace the file
+    let bad = format!("{{\"uuid\": \"{}\", \"entities\": 5}}", original.uuid().to_hyphenated_string());
+    match hairball_tools::parse(&bad)
+        .and_then(|json| hairball_tools::from_json(&json, "hairballs/json_loaded.hairball")) {
+        Err(hairball_tools::TextError::Invalid(_)) => (),
+        _ => panic!("expected the entities to be invalid")
+    }
+    let loaded = Reader::read("hairballs/json_loaded.hairball").unwrap();
+    assert_eq!(original.entities_len(), loaded.entities_len());
+    assert!(std::fs::metadata("hairballs/json_loaded.hairball.tmp").is_err());
+}
+
+#[cfg(feature="build-schema")]
+#[test]
+fn dump_embedded_schema() {
+    use hairball_geometry::Geometry;
+    use hairball_material::{Component, Value};
+
+    let mut hairball = Builder::new("hairballs/dump.hairball").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    let red = hairball.add_entity(LocalEntity::named("red".to_owned()));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 6, length: 36 })
+    ]);
+    hairball_material::write(&mut hairball, &[
+        (red, Component::Diffuse, Value::Color([1., 0.25, 0., 1.])),
+        (red, Component::Ambient, Value::Texture(cube))
+    ]);
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/dump.hairball").unwrap();
+    let mut geometry = Vec::new();
+    hairball_tools::dump(&hairball, hairball_geometry::COLUMN_NAME, &mut geometry).unwrap();
+    assert_eq!("geometries = [\n  (id = 0, mesh = 0, start = 6, length = 36)\n]\n",
+               String::from_utf8(geometry).unwrap());
+
+    let mut material = Vec::new();
+    hairball_tools::dump(&hairball, hairball_material::COLUMN_NAME, &mut material).unwrap();
+    assert_eq!(concat!("bindings = [\n",
+                       "  (id = 1, component = diffuse, color = (red = 1, green = 0.25, blue = 0, alpha = 1))\n",
+                       "  (id = 1, component = ambient, texture = 0)\n",
+                       "]\n"),
+               String::from_utf8(material).unwrap());
+}
+
+#[test]
+fn column_writers_on_threads() {
+    use hairball::Columns;
+
+    fn row(t: usize, i: usize) -> String {
+        std::iter::repeat(format!("{}:{} ", t, i)).take(1024).collect()
+    }
+
+    // each column needs several segments, so the two threads allocate
+    // segments of the container at the same time
+    let mut hairball = Builder::new("hairballs/column_writer_threads.hairball").unwrap();
+    let threads: Vec<_> = (0..2).map(|t| {
+        let mut writer = hairball.column_writer(&format!("column_{}", t));
+        std::thread::spawn(move || {
+            {
+                let name = writer.name().to_owned();
+                let builder = writer.column(&name).unwrap();
+                let mut rows = builder.initn_as::<capnp::text_list::Builder>(16);
+                for i in 0..16 {
+                    rows.set(i, &row(t, i as usize));
+                }
+            }
+            writer
+        })
+    }).collect();
+
+    for t in threads {
+        hairball.commit(t.join().unwrap());
+    }
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/column_writer_threads.hairball").unwrap();
+    for t in 0..2 {
+        let rows = hairball.column(&format!("column_{}", t)).unwrap()
+            .get_as::<capnp::text_list::Reader>().unwrap();
+        assert_eq!(16, rows.len());
+        for i in 0..16 {
+            assert_eq!(row(t, i as usize), rows.get(i).unwrap());
+        }
+    }
+}
+
+#[test]
+fn drop_column_writer() {
+    use hairball::Columns;
+
+    let write = |writer: &mut hairball::ColumnWriter| {
+        let name = writer.name().to_owned();
+        let builder = writer.column(&name).unwrap();
+        let s: String = std::iter::repeat("column \\o/").take(1024).collect();
+        let mut text = builder.initn_as::<capnp::text::Builder>(s.len() as u32);
+        text.push_str(&s);
+    };
+
+    let mut hairball = Builder::new("hairballs/column_writer_once.hairball").unwrap();
+    let mut writer = hairball.column_writer("column");
+    write(&mut writer);
+    hairball.commit(writer);
+    hairball.close().unwrap();
+
+    // the segments of the dropped writer are used by the committed one
+    let mut hairball = Builder::new("hairballs/column_writer_dropped.hairball").unwrap();
+    let mut dropped = hairball.column_writer("column");
+    write(&mut dropped);
+    drop(dropped);
+    let mut writer = hairball.column_writer("column");
+    write(&mut writer);
+    hairball.commit(writer);
+    hairball.close().unwrap();
+
+    assert_eq!(std::fs::metadata("hairballs/column_writer_once.hairball").unwrap().len(),
+               std::fs::metadata("hairballs/column_writer_dropped.hairball").unwrap().len());
+    let hairball = Reader::read("hairballs/column_writer_dropped.hairball").unwrap();
+    assert_eq!(vec!["column"], hairball.column_names());
+}
+
+#[test]
+fn shared_reader() {
+    use std::sync::Arc;
+
+    let mut hairball = Builder::new("hairballs/shared_reader.hairball").unwrap();
+    for i in 0..4 {
+        let builder = hairball.column(&format!("column_{}", i)).unwrap();
+        let s = format!("column_{} \\o/", i);
+        let mut text = builder.initn_as::<capnp::text::Builder>(s.len() as u32);
+        text.push_str(&s);
+    }
+    hairball.close().unwrap();
+
+    // a reader can be shared between threads, each column is read first
+    // by a different thread
+    let hairball = Arc::new(Reader::read("hairballs/shared_reader.hairball").unwrap());
+    let threads: Vec<_> = (0..4).map(|i| {
+        let hairball = hairball.clone();
+        std::thread::spawn(move || {
+            let text = hairball.column(&format!("column_{}", i)).unwrap()
+                .get_as::<capnp::text::Reader>().unwrap();
+            assert_eq!(format!("column_{} \\o/", i), text);
+        })
+    }).collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[test]
+fn replace_column_reuses_segments() {
+    let write = |path: &str, times: usize| {
+        let mut hairball = Builder::new(path).unwrap();
+        let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+        for _ in 0..times {
+            hairball.remove_column(hairball_mesh_index::COLUMN_NAME);
+            hairball_mesh_index::write_owned(&mut hairball, &[(cube, (0..4096).collect())]);
+        }
+        hairball.close().unwrap();
+    };
+    write("hairballs/replace_once.hairball", 1);
+    write("hairballs/replace_many.hairball", 5);
+
+    let once = Reader::read("hairballs/replace_once.hairball").unwrap();
+    let many = Reader::read("hairballs/replace_many.hairball").unwrap();
+    assert_eq!(once.segments().len(), many.segments().len());
+    assert!(many.segments().iter().all(|s| s.message.is_some()));
+    assert_eq!(std::fs::metadata("hairballs/replace_once.hairball").unwrap().len(),
+               std::fs::metadata("hairballs/replace_many.hairball").unwrap().len());
+    assert_eq!(hairball_mesh_index::lookup(&hairball::Link { reader: &many, entity: 0 }),
+               Some((0..4096).collect()));
+}
+
+#[test]
+fn repack_and_verify() {
+    use hairball_geometry::Geometry;
+
+    let mut hairball = Builder::new("hairballs/repack.hairball").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    let sphere = hairball.add_entity(LocalEntity::named("sphere".to_owned()));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    hairball_geometry::write(&mut hairball, &[
+        (sphere, Geometry{ mesh: sphere, start: 0, length: 960 })
+    ]);
+    hairball.delete_rows(hairball_material::COLUMN_NAME, &[cube]).unwrap();
+    hairball.set_revision(2);
+    hairball.close().unwrap();
+
+    let original = Reader::read("hairballs/repack.hairball").unwrap();
+    assert_eq!(2, original.chunks(hairball_geometry::COLUMN_NAME).len());
+    assert_eq!(0, hairball_tools::verify(&original).len());
+    assert!(original.segments().len() > 0);
+    assert_eq!(Some(0), original.segments()[0].message);
+
+    let mut repacked = Builder::new_with_uuid("hairballs/repacked.hairball", original.uuid()).unwrap();
+    hairball_tools::repack(&original, &mut repacked).unwrap();
+    repacked.close().unwrap();
+
+    let repacked = Reader::read("hairballs/repacked.hairball").unwrap();
+    assert_eq!(original.uuid(), repacked.uuid());
+    assert_eq!(original.version(), repacked.version());
+    assert_eq!(2, repacked.revision());
+    assert_eq!(1, repacked.chunks(hairball_geometry::COLUMN_NAME).len());
+    assert!(repacked.column_sorted(hairball_geometry::COLUMN_NAME));
+    assert_eq!(vec![cube], repacked.tombstones(hairball_material::COLUMN_NAME));
+    assert!(hairball_tools::diff(&original, &repacked).is_empty());
+
+    // a row that references a missing entity is a problem
+    let mut hairball = Builder::new("hairballs/verify.hairball").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: 5, start: 0, length: 36 })
+    ]);
+    hairball.close_unchecked().unwrap();
+
+    let hairball = Reader::read("hairballs/verify.hairball").unwrap();
+    assert_eq!(1, hairball_tools::verify(&hairball).len());
+}
+
+#[test]
+fn stats() {
+    use hairball_geometry::Geometry;
+
+    let mut hairball = Builder::new("hairballs/stats.hairball").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()));
+    let sphere = hairball.add_entity(LocalEntity::named("sphere_of_sixteen".to_owned()));
+    hairball.add_entity(LocalEntity::anonymous());
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    let mut index = hairball.column_writer(hairball_mesh_index::COLUMN_NAME);
+    hairball_mesh_index::write_owned(&mut index, &[
+        (cube, (0..36).collect()),
+        (sphere, (0..960).collect())
+    ]);
+    hairball.commit(index);
+
+    // the rewritten index reuses the segments of the removed one, the
+    // writer that is never committed leaves a message no column uses
+    hairball.remove_column(hairball_mesh_index::COLUMN_NAME);
+    let mut index = hairball.column_writer(hairball_mesh_index::COLUMN_NAME);
+    hairball_mesh_index::write_owned(&mut index, &[
+        (cube, (0..36).collect()),
+        (sphere, (0..960).collect())
+    ]);
+    hairball.commit(index);
+    let mut dropped = hairball.column_writer(hairball_mesh_index::COLUMN_NAME);
+    hairball_mesh_index::write_owned(&mut dropped, &[
+        (cube, (0..36).collect())
+    ]);
+    drop(dropped);
+    hairball.close().unwrap();
+
+    let hairball = Reader::read("hairballs/stats.hairball").unwrap();
+    let stats = hairball.stats().unwrap();
+
+    // "cube" fits in one word, "sphere_of_sixteen" and it's nul in three
+    assert_eq!(4, stats.names);
+    assert_eq!(2, stats.columns.len());
+    let columns = stats.columns.iter().fold(0, |a, &(_, words)| a + words);
+    assert_eq!(stats.segments, stats.entities + stats.names + stats.schemas +
+                               stats.tables + stats.padding + stats.unused + columns);
+    assert!(stats.padding > 0);
+    assert!(stats.unused > 0);
+
+    let indices = hairball_mesh_index::stats(&hairball).unwrap();
+    assert_eq!(2, indices.len());
+    assert_eq!(cube, indices[0].0);
+    assert!(indices[1].1 >= 480);
+    let index = stats.columns.iter()
+        .find(|&&(ref name, _)| name == hairball_mesh_index::COLUMN_NAME)
+        .unwrap().1;
+    assert!(index >= indices[0].1 + indices[1].1);
+}
+
+#[test]
+fn ffi() {
+    use std::ffi::CString;
+    use std::ptr;
+    use hairball_ffi::*;
+    use hairball_geometry::Geometry;
+    use hairball_material::{Component, Value};
+
+    let mut hairball = Builder::new("hairballs/ffi.hairball").unwrap();
+    let geometry = hairball.add_entity_at_path("geometry").unwrap();
+    let cube = hairball.add_entity(LocalEntity::named("cube".to_owned()).parent(geometry));
+    hairball_geometry::write(&mut hairball, &[
+        (cube, Geometry{ mesh: cube, start: 0, length: 36 })
+    ]);
+    hairball_mesh_index::write_owned(&mut hairball, &[(cube, (0..36).collect())]);
+    hairball_material::write(&mut hairball, &[
+        (cube, Component::Diffuse, Value::Color([1., 0., 0., 1.])),
+        (cube, Component::Ambient, Value::Texture(3))
+    ]);
+    hairball.delete_rows("mesh", &[cube]).unwrap();
+    let uuid = hairball.uuid();
+    hairball.close().unwrap();
+
+    unsafe {
+        let missing = CString::new("hairballs/missing.hairball").unwrap();
+        assert!(hairball_open(missing.as_ptr()).is_null());
+
+        let path = CString::new("hairballs/ffi.hairball").unwrap();
+        let hb = hairball_open(path.as_ptr());
+        assert!(!hb.is_null());
+
+        let mut bytes = [0; 16];
+        assert!(hairball_uuid(hb, bytes.as_mut_ptr()));
+        assert_eq!(uuid.as_bytes(), &bytes[..]);
+        assert_eq!(2, hairball_entities_len(hb));
+
+        let mut entity: u32 = 0;
+        let name = CString::new("geometry/cube").unwrap();
+        assert!(hairball_lookup(hb, name.as_ptr(), &mut entity));
+        assert_eq!(cube, entity);
+
+        let mut e: HairballEntity = std::mem::zeroed();
+        assert!(hairball_entity(hb, cube, &mut e));
+        assert_eq!(4, e.name_len);
+        assert!(e.has_parent);
+        assert_eq!(geometry, e.parent);
+        assert!(!e.external);
+        assert!(!hairball_entity(hb, 2, &mut e));
+
+        // the path is truncated to fit the buffer
+        let mut buf = [0 as std::os::raw::c_char; 9];
+        assert_eq!(13, hairball_entity_path(hb, cube, buf.as_mut_ptr(), 9));
+        assert_eq!(b"geometry", std::ffi::CStr::from_ptr(buf.as_ptr()).to_bytes());
+
+        let mut g = HairballGeometry { mesh: 0, start: 0, length: 0 };
+        assert!(hairball_geometry(hb, cube, &mut g));
+        assert_eq!((cube, 0, 36), (g.mesh, g.start, g.length));
+        assert!(!hairball_geometry(hb, geometry, &mut g));
+
+        assert_eq!(36, hairball_mesh_index(hb, cube, ptr::null_mut(), 0));
+        let mut index = vec![0; 36];
+        assert_eq!(36, hairball_mesh_index(hb, cube, index.as_mut_ptr(), 36));
+        assert_eq!((0..36).collect::<Vec<u32>>(), index);
+        assert_eq!(0, hairball_mesh_buffers(hb, cube));
+
+        let mut materials: Vec<HairballMaterial> = (0..2).map(|_| std::mem::zeroed()).collect();
+        assert_eq!(2, hairball_materials(hb, cube, materials.as_mut_ptr(), 2));
+        let texture = materials.iter().find(|m| m.kind == 1).unwrap();
+        assert_eq!((0, 3), (texture.component, texture.texture));
+
+        // the tombstones of the mesh column are not a column
+        assert_eq!(3, hairball_columns_len(hb));
+        let column = CString::new("geometry").unwrap();
+        assert!(hairball_has_column(hb, column.as_ptr()));
+        let tombstones = CString::new("mesh.tombstone").unwrap();
+        assert!(!hairball_has_column(hb, tombstones.as_ptr()));
+
+        hairball_close(hb);
+    }
+}